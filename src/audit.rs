@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::config::CONFIG;
+
+lazy_static! {
+    // Keeps concurrent entries from interleaving
+    static ref AUDIT_LOG: Mutex<()> = Mutex::new(());
+}
+
+// A moderation action taken through the bot or the web library, which has no channel or message
+#[derive(Serialize)]
+pub struct AuditEntry<'a> {
+    pub user_id: u64,
+    pub user_name: &'a str,
+    pub action: &'a str,
+    pub guild_id: Option<u64>,
+    pub channel_id: Option<u64>,
+    pub message_id: Option<u64>,
+    pub video_id: Option<&'a str>,
+}
+
+// Append an entry to the audit log as a line of JSON with the time it happened
+pub async fn record(entry: &AuditEntry<'_>) -> Result<()> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut line = serde_json::to_value(entry)?;
+    line["time"] = time.into();
+    let mut line = serde_json::to_vec(&line)?;
+    line.push(b'\n');
+
+    let _guard = AUDIT_LOG.lock().await;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&CONFIG.audit_log_file)
+        .await
+        .with_context(|| format!("Failed to open {}", CONFIG.audit_log_file))?;
+    file.write_all(&line).await?;
+    file.sync_data().await?;
+
+    match entry.channel_id {
+        Some(channel_id) => info!(
+            "{} ({}) {} in channel {}",
+            entry.user_name, entry.user_id, entry.action, channel_id
+        ),
+        None => info!(
+            "{} ({}) {} on the web",
+            entry.user_name, entry.user_id, entry.action
+        ),
+    }
+    Ok(())
+}
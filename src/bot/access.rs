@@ -0,0 +1,69 @@
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::permissions::Permissions;
+use serenity::prelude::*;
+
+use crate::config::CONFIG;
+use crate::settings::SETTINGS;
+
+// Whether the bot should process messages from where this one was posted
+pub async fn is_allowed(ctx: &Context, msg: &Message) -> bool {
+    let Some(guild_id) = msg.guild_id else {
+        return CONFIG.allow_dms;
+    };
+
+    let guild = guild_id.get();
+    if CONFIG.denied_guilds.contains(&guild)
+        || (!CONFIG.allowed_guilds.is_empty() && !CONFIG.allowed_guilds.contains(&guild))
+    {
+        return false;
+    }
+
+    let settings = SETTINGS.get(guild).await;
+    if settings.disabled {
+        return false;
+    }
+
+    // Threads follow the lists of their parent channel unless they are listed themselves
+    let channel = msg.channel_id.get();
+    let parent = thread_parent(ctx, guild_id, msg.channel_id).map(|parent| parent.get());
+    let listed = |channels: &[u64]| {
+        channels.contains(&channel) || parent.is_some_and(|parent| channels.contains(&parent))
+    };
+
+    if listed(&CONFIG.denied_channels) || listed(&settings.denied_channels) {
+        return false;
+    }
+
+    (CONFIG.allowed_channels.is_empty() || listed(&CONFIG.allowed_channels))
+        && (settings.allowed_channels.is_empty() || listed(&settings.allowed_channels))
+}
+
+// Parent of a thread from the cache, None for channels that aren't threads
+fn thread_parent(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> Option<ChannelId> {
+    let guild = ctx.cache.guild(guild_id)?;
+    guild
+        .threads
+        .iter()
+        .find(|thread| thread.id == channel_id)
+        .and_then(|thread| thread.parent_id)
+}
+
+// Whether the message was posted in a thread
+pub fn in_thread(ctx: &Context, msg: &Message) -> bool {
+    msg.guild_id
+        .is_some_and(|guild_id| thread_parent(ctx, guild_id, msg.channel_id).is_some())
+}
+
+// The bot's permissions where the message was posted, None outside guilds or if not cached
+pub fn bot_permissions(ctx: &Context, msg: &Message) -> Option<Permissions> {
+    let guild_id = msg.guild_id?;
+    // Threads inherit the permission overwrites of their parent
+    let channel_id = thread_parent(ctx, guild_id, msg.channel_id).unwrap_or(msg.channel_id);
+    let bot_id = ctx.cache.current_user().id;
+
+    let guild = ctx.cache.guild(guild_id)?;
+    let channel = guild.channels.get(&channel_id)?;
+    let member = guild.members.get(&bot_id)?;
+    Some(guild.user_permissions_in(channel, member))
+}
@@ -7,7 +7,9 @@ use crate::bot::handler::Handler;
 use crate::config::CONFIG;
 
 pub async fn start_bot(shutdown_signal: Arc<Notify>) -> Result<(), Box<dyn std::error::Error>> {
-    let intents = GatewayIntents::GUILD_MESSAGES
+    // Guilds fill the cache that threads are resolved to their parent channel from
+    let intents = GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT;
 
@@ -17,17 +19,14 @@ pub async fn start_bot(shutdown_signal: Arc<Notify>) -> Result<(), Box<dyn std::
 
     let shard_manager = client.shard_manager.clone();
 
-    tokio::spawn(async move {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Failed to listen for Ctrl+C");
-        info!("Ctrl+C received, shutting down bot");
-        shard_manager.shutdown_all().await;
-        shutdown_signal.notify_waiters();
-    });
-
     info!("Starting Discord bot");
-    client.start().await?;
+    tokio::select! {
+        result = client.start() => result?,
+        _ = shutdown_signal.notified() => {
+            info!("Shutdown signal received, stopping bot");
+            shard_manager.shutdown_all().await;
+        }
+    }
 
     Ok(())
 }
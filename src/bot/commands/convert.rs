@@ -1,11 +1,35 @@
 use anyhow::Result;
 use lazy_static::lazy_static;
 use regex::Regex;
-use serenity::model::channel::Message;
+use serenity::builder::{
+    CreateAllowedMentions, CreateAttachment, CreateMessage, CreateThread, EditMessage,
+};
+use serenity::http::Http;
+use serenity::model::channel::{
+    AutoArchiveDuration, Channel, ChannelType, Message, PermissionOverwriteType,
+};
+use serenity::model::id::{ChannelId, MessageId};
+use serenity::model::permissions::Permissions;
 use serenity::prelude::*;
+use std::path::Path;
 use tracing::{error, info};
 
+use crate::bot::access::{bot_permissions, in_thread};
+use crate::bot::commands::DeleteButtons;
+use crate::bot::services::pipeline::run_job;
+use crate::bot::services::probe::{check_duration, Rejection};
+use crate::bot::services::upload::{prepare_upload, upload_limit};
 use crate::bot::services::yliproxy::YliProxy;
+use crate::config::CONFIG;
+use crate::jobs::{Origin, JOBS};
+use crate::metadata::{resolve_alias, VideoMetadata};
+use crate::metrics::MESSAGES_MATCHED;
+use crate::ratelimit::{acquire_conversion, Throttled};
+use crate::settings::{GuildSettings, ReactionStyle, ReplyMode, SETTINGS};
+use crate::shutdown;
+
+// Name guilds enable or disable this provider by
+pub const PROVIDER: &str = "ylilauta";
 
 lazy_static! {
     static ref MP4_PATTERN: Regex = Regex::new(r"https://.+\.ylilauta\.org/.+\.mp4").unwrap();
@@ -16,51 +40,362 @@ pub struct YliProxyHandler;
 impl YliProxyHandler {
     pub async fn handle(ctx: &Context, msg: &Message) -> bool {
         // Check if message contains a Ylilauta video URL
-        if let Some(captures) = MP4_PATTERN.captures(&msg.content) {
-            if let Some(url) = captures.get(0) {
-                info!("Found Ylilauta video URL: {}", url.as_str());
-                let process_reaction = msg.react(&ctx.http, '⏳').await.unwrap();
+        if let Some(captures) = MP4_PATTERN.captures(&msg.content)
+            && let Some(url) = captures.get(0)
+        {
+            let settings = SETTINGS
+                .for_guild(msg.guild_id.map(|guild_id| guild_id.get()))
+                .await;
+            if !settings.provider_enabled(PROVIDER) {
+                return false;
+            }
+
+            info!("Found Ylilauta video URL: {}", url.as_str());
+            MESSAGES_MATCHED.inc();
+
+            // Held until the reply is sent so shutdown waits for it
+            let Some(_in_flight) = shutdown::accept() else {
+                info!("Shutting down, ignoring {}", url.as_str());
+                return true;
+            };
+            let process_reaction = match settings.reactions {
+                ReactionStyle::Full => match msg.react(&ctx.http, '⏳').await {
+                    Ok(reaction) => Some(reaction),
+                    Err(e) => {
+                        error!("Error adding reaction: {:?}", e);
+                        None
+                    }
+                },
+                ReactionStyle::Minimal | ReactionStyle::None => None,
+            };
 
-                if let Err(e) = Self::process_video(ctx, msg, url.as_str()).await {
-                    error!("Error processing video: {:?}", e);
+            if let Err(e) = Self::process_video(ctx, msg, url.as_str(), &settings).await {
+                error!("Error processing video: {:?}", e);
+                if settings.reactions != ReactionStyle::None {
                     msg.react(&ctx.http, '❌').await.ok();
                 }
 
-                if let Err(e) = process_reaction.delete(&ctx.http).await {
-                    error!("Error removing reactions: {:?}", e);
+                // Tell the requester why their file was refused
+                if let Some(rejection) = e.downcast_ref::<Rejection>() {
+                    msg.reply(
+                        &ctx.http,
+                        format!("Can't convert this video: {}", rejection),
+                    )
+                    .await
+                    .ok();
+                } else if let Some(throttled) = e.downcast_ref::<Throttled>() {
+                    msg.reply(&ctx.http, format!("Sorry, {}.", throttled))
+                        .await
+                        .ok();
                 }
+            }
 
-                return true;
+            if let Some(process_reaction) = process_reaction
+                && let Err(e) = process_reaction.delete(&ctx.http).await
+            {
+                error!("Error removing reactions: {:?}", e);
             }
+
+            return true;
         }
 
         false
     }
 
-    async fn process_video(ctx: &Context, msg: &Message, url: &str) -> Result<()> {
+    // Answer a request whose job was resumed after a restart, with only a link as the
+    // original message and its guild settings aren't at hand
+    pub async fn reply_resumed(http: &Http, origin: &Origin, result: Result<VideoMetadata>) {
+        let (Some(channel_id), Some(message_id)) = (origin.channel_id, origin.message_id) else {
+            return;
+        };
+        let channel_id = ChannelId::new(channel_id);
+        let message_id = MessageId::new(message_id);
+
+        // The hourglass of the interrupted attempt is still on the message
+        if let Err(e) = channel_id
+            .delete_reaction(http, message_id, None, '⏳')
+            .await
+        {
+            info!("Could not remove reaction from {}: {:?}", message_id, e);
+        }
+
+        let metadata = match result {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                channel_id
+                    .create_reaction(http, message_id, '❌')
+                    .await
+                    .ok();
+                return;
+            }
+        };
+
+        let reply = async {
+            let mut message = CreateMessage::new()
+                .content(YliProxy::get_reply_url(&metadata).await?)
+                .reference_message((channel_id, message_id))
+                .allowed_mentions(CreateAllowedMentions::new().replied_user(false));
+            if CONFIG.delete_buttons
+                && let Some(requester_id) = origin.submitter_id
+            {
+                message = message.components(DeleteButtons::components(requester_id, &metadata));
+            }
+            channel_id.send_message(http, message).await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        if let Err(e) = reply.await {
+            error!("Failed to reply to resumed job {}: {:?}", metadata.id, e);
+        }
+    }
+
+    async fn process_video(
+        ctx: &Context,
+        msg: &Message,
+        url: &str,
+        settings: &GuildSettings,
+    ) -> Result<()> {
         let id = match YliProxy::extract_id_from_url(url) {
             Ok(id) => id,
             Err(e) => return Err(e),
         };
 
-        // Check if file already exists
-        if let Some(file_url) = YliProxy::get_existing_file_url(&id).await {
+        // Reposts that were deduplicated before resolve to the stored video
+        let id = resolve_alias(&id).await.unwrap_or(id);
+
+        let origin = Origin {
+            private: Self::is_private_channel(ctx, msg).await,
+            guild_id: msg.guild_id.map(|guild_id| guild_id.get()),
+            channel_id: Some(msg.channel_id.get()),
+            message_id: Some(msg.id.get()),
+            submitter_id: Some(msg.author.id.get()),
+        };
+
+        // Check if file already exists, it's shared whatever profile this guild picked
+        if let Some(mut metadata) = YliProxy::get_existing_metadata(&id).await {
             info!("Using existing converted file for ID: {}", id);
-            msg.channel_id.say(&ctx.http, file_url).await?;
+
+            // The guild's duration limit may be lower than the one it was converted under
+            if let Some(duration) = metadata.duration {
+                check_duration(duration, settings.max_duration())?;
+            }
+
+            // Reposts are listed under every guild they were posted in
+            let updated = VideoMetadata::update(&id, |metadata| {
+                metadata.add_posting(&origin);
+            })
+            .await;
+            let saved = match updated {
+                Ok(Some(updated)) => {
+                    metadata = updated;
+                    Ok(())
+                }
+                // Files converted before metadata was tracked get it now
+                Ok(None) => {
+                    metadata.add_posting(&origin);
+                    metadata.save().await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = saved {
+                error!("Failed to record posting of {}: {:?}", id, e);
+            }
+
+            Self::reply(ctx, msg, &metadata, settings).await?;
             return Ok(());
         }
 
-        // Process new file
-        let file_path = YliProxy::download_file(url).await?;
-        let output_file = YliProxy::convert_to_h264(&file_path, &id).await?;
+        // Only new conversions count towards the limits, existing videos and joining one
+        // that is already being converted are cheap
+        if !JOBS.is_running(&id).await {
+            acquire_conversion(
+                msg.author.id.get(),
+                msg.channel_id.get(),
+                msg.guild_id.map(|guild_id| guild_id.get()),
+            )
+            .await?;
+        }
+
+        let metadata = run_job(url, &id, origin).await?;
+        Self::reply(ctx, msg, &metadata, settings).await?;
+
+        Ok(())
+    }
+
+    // Whether the message was posted somewhere not everyone in the server can see
+    async fn is_private_channel(ctx: &Context, msg: &Message) -> bool {
+        // Direct messages are always private
+        let Some(guild_id) = msg.guild_id else {
+            return true;
+        };
+
+        let channel = match msg.channel(ctx).await {
+            Ok(Channel::Guild(channel)) => channel,
+            Ok(_) => return true,
+            Err(e) => {
+                // Err on the side of not exposing the video
+                error!("Failed to fetch channel {}: {:?}", msg.channel_id, e);
+                return true;
+            }
+        };
+
+        if channel.kind == ChannelType::PrivateThread {
+            return true;
+        }
+
+        // Public threads share their parent channel's visibility
+        let channel = match channel.thread_metadata.and(channel.parent_id) {
+            Some(parent_id) => match parent_id.to_channel(ctx).await {
+                Ok(Channel::Guild(parent)) => parent,
+                _ => return true,
+            },
+            None => channel,
+        };
+
+        channel.permission_overwrites.iter().any(|overwrite| {
+            overwrite.kind == PermissionOverwriteType::Role(guild_id.everyone_role())
+                && overwrite.deny.contains(Permissions::VIEW_CHANNEL)
+        })
+    }
+
+    // Reply with the video attached if possible, or with a link to it
+    async fn reply(
+        ctx: &Context,
+        msg: &Message,
+        metadata: &VideoMetadata,
+        settings: &GuildSettings,
+    ) -> Result<()> {
+        let permissions = bot_permissions(ctx, msg);
+        let (channel_id, mut message) = Self::destination(ctx, msg, settings, permissions).await;
+        if CONFIG.delete_buttons {
+            message = message.components(DeleteButtons::components(msg.author.id.get(), metadata));
+        }
+        Self::send(ctx, msg, channel_id, message, metadata).await?;
+
+        // The original embed can't play the video, hide it now that a working one is posted.
+        // Only possible in guilds, and unknown permissions are left to the request to fail.
+        // Discord often adds the embed after the message arrives, so it's suppressed regardless
+        if settings.suppress_embeds() && msg.guild_id.is_some() {
+            if permissions.is_some_and(|permissions| !permissions.manage_messages()) {
+                info!(
+                    "Missing Manage Messages in {}, not suppressing embeds",
+                    msg.channel_id
+                );
+            } else if let Err(e) = msg
+                .channel_id
+                .edit_message(&ctx.http, msg.id, EditMessage::new().suppress_embeds(true))
+                .await
+            {
+                error!("Failed to suppress embeds of {}: {:?}", msg.id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Channel to post the video in and the message to post it with, falling back to
+    // replying in place when a thread can't be used
+    async fn destination(
+        ctx: &Context,
+        msg: &Message,
+        settings: &GuildSettings,
+        permissions: Option<Permissions>,
+    ) -> (ChannelId, CreateMessage) {
+        let reply = CreateMessage::new()
+            .reference_message(msg)
+            .allowed_mentions(CreateAllowedMentions::new().replied_user(false));
+
+        match settings.reply_mode() {
+            ReplyMode::Channel => (msg.channel_id, CreateMessage::new()),
+            ReplyMode::Reply => (msg.channel_id, reply),
+            ReplyMode::Thread => {
+                // A thread was already started on the message
+                if let Some(thread) = &msg.thread {
+                    return (thread.id, CreateMessage::new());
+                }
+
+                let can_create = permissions.is_none_or(|permissions| {
+                    permissions.create_public_threads() && permissions.send_messages_in_threads()
+                });
+                if msg.guild_id.is_none() || in_thread(ctx, msg) || !can_create {
+                    return (msg.channel_id, reply);
+                }
+
+                let thread = CreateThread::new("Converted video")
+                    .auto_archive_duration(AutoArchiveDuration::OneDay);
+                match msg
+                    .channel_id
+                    .create_thread_from_message(&ctx.http, msg.id, thread)
+                    .await
+                {
+                    Ok(thread) => (thread.id, CreateMessage::new()),
+                    Err(e) => {
+                        error!("Failed to create a thread for {}: {:?}", msg.id, e);
+                        (msg.channel_id, reply)
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send(
+        ctx: &Context,
+        msg: &Message,
+        channel_id: ChannelId,
+        message: CreateMessage,
+        metadata: &VideoMetadata,
+    ) -> Result<()> {
+        if CONFIG.discord_upload_enabled {
+            let limit = upload_limit(ctx, msg).await;
+
+            match prepare_upload(metadata, limit).await {
+                Ok(Some(upload)) => {
+                    let result = Self::send_attachment(
+                        ctx,
+                        channel_id,
+                        message.clone(),
+                        &upload.path,
+                        &metadata.id,
+                    )
+                    .await;
+                    upload.cleanup().await;
+
+                    match result {
+                        Ok(()) => return Ok(()),
+                        Err(e) => error!(
+                            "Failed to upload {}, replying with a link: {:?}",
+                            metadata.id, e
+                        ),
+                    }
+                }
+                Ok(None) => info!(
+                    "{} doesn't fit the upload limit, replying with a link",
+                    metadata.id
+                ),
+                Err(e) => error!("Failed to prepare upload for {}: {:?}", metadata.id, e),
+            }
+        }
+
+        let file_url = YliProxy::get_reply_url(metadata).await?;
+        channel_id
+            .send_message(&ctx.http, message.content(file_url))
+            .await?;
+
+        Ok(())
+    }
 
-        let file_name = output_file
-            .file_name()
-            .and_then(|n| n.to_str())
-            .ok_or_else(|| anyhow::anyhow!("Invalid output filename"))?;
+    async fn send_attachment(
+        ctx: &Context,
+        channel_id: ChannelId,
+        message: CreateMessage,
+        path: &Path,
+        id: &str,
+    ) -> Result<()> {
+        let mut attachment = CreateAttachment::path(path).await?;
+        attachment.filename = format!("{}.mp4", id);
 
-        let file_url = YliProxy::get_file_url(file_name);
-        msg.channel_id.say(&ctx.http, file_url).await?;
+        channel_id
+            .send_message(&ctx.http, message.add_file(attachment))
+            .await?;
 
         Ok(())
     }
@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result};
+use serenity::all::{
+    ButtonStyle, ComponentInteraction, CreateActionRow, CreateButton, EditInteractionResponse,
+};
+use serenity::prelude::*;
+use tracing::error;
+
+use crate::audit::{self, AuditEntry};
+use crate::config::CONFIG;
+use crate::library::delete_video;
+use crate::metadata::{Posting, VideoMetadata};
+
+// Custom ID prefix of the buttons, followed by ":{action}:{requester}:{video}"
+pub const BUTTON_PREFIX: &str = "delete";
+
+pub struct DeleteButtons;
+
+impl DeleteButtons {
+    // Buttons added to a reply, deleting the video is only offered to those who posted it
+    pub fn components(requester_id: u64, metadata: &VideoMetadata) -> Vec<CreateActionRow> {
+        let custom_id = |action: &str| {
+            format!(
+                "{}:{}:{}:{}",
+                BUTTON_PREFIX, action, requester_id, metadata.id
+            )
+        };
+
+        let mut buttons = vec![CreateButton::new(custom_id("reply"))
+            .label("Delete")
+            .emoji('🗑')
+            .style(ButtonStyle::Secondary)];
+        if metadata.submitter_ids().contains(&requester_id) {
+            buttons.push(
+                CreateButton::new(custom_id("video"))
+                    .label("Delete video")
+                    .style(ButtonStyle::Danger),
+            );
+        }
+
+        vec![CreateActionRow::Buttons(buttons)]
+    }
+
+    pub async fn handle(ctx: &Context, component: &ComponentInteraction) {
+        // Removing stored files can take longer than an interaction may go unanswered
+        if let Err(e) = component.defer_ephemeral(&ctx.http).await {
+            error!("Error acknowledging delete button: {:?}", e);
+            return;
+        }
+
+        let content = match Self::run(ctx, component).await {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Error deleting reply {}: {:?}", component.message.id, e);
+                format!("Something went wrong: {}", e)
+            }
+        };
+
+        let response = EditInteractionResponse::new().content(content);
+        if let Err(e) = component.edit_response(&ctx.http, response).await {
+            error!("Error responding to delete button: {:?}", e);
+        }
+    }
+
+    async fn run(ctx: &Context, component: &ComponentInteraction) -> Result<String> {
+        let mut parts = component.data.custom_id.splitn(4, ':').skip(1);
+        let (Some(action), Some(requester_id), Some(video_id)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(anyhow!("Malformed button {}", component.data.custom_id));
+        };
+        let requester_id: u64 = requester_id.parse()?;
+
+        let user_id = component.user.id.get();
+        let is_admin = CONFIG.admin_user_ids.contains(&user_id);
+        // Permissions in the channel the reply was posted in
+        let is_moderator = component
+            .member
+            .as_ref()
+            .and_then(|member| member.permissions)
+            .is_some_and(|permissions| permissions.manage_messages());
+        if user_id != requester_id && !is_moderator && !is_admin {
+            return Ok(
+                "Only the person who posted the video or moderators can delete this.".to_string(),
+            );
+        }
+
+        let guild_id = component.guild_id.map(|guild_id| guild_id.get());
+        let (action, content) = match action {
+            "reply" => ("delete_reply", "Deleted."),
+            "video" => {
+                let Some(metadata) = VideoMetadata::load(video_id).await else {
+                    return Ok("The video was already deleted.".to_string());
+                };
+
+                // Users answer for their own postings and moderators for their server's
+                let own = |posting: &Posting| {
+                    posting.submitter_id == Some(user_id)
+                        || (is_moderator && guild_id.is_some() && posting.guild_id == guild_id)
+                };
+                let postings = metadata.all_postings();
+                if !is_admin && !postings.iter().any(own) {
+                    return Ok("Only the people who posted the video can delete it.".to_string());
+                }
+
+                // Videos reused by other posts stay, only this association is dropped.
+                // Reposts recorded before postings were tracked only left an alias
+                let shared = postings.iter().any(|posting| !own(posting))
+                    || (metadata.postings.is_empty() && !metadata.aliases.is_empty());
+                if is_admin || !shared {
+                    delete_video(&metadata).await?;
+                    ("delete_video", "Deleted the reply and the video.")
+                } else if metadata.postings.is_empty() {
+                    return Ok(
+                        "The video was also posted elsewhere, ask an administrator to delete it."
+                            .to_string(),
+                    );
+                } else {
+                    VideoMetadata::update(video_id, |metadata| metadata.remove_postings(own))
+                        .await?;
+                    (
+                        "detach_video",
+                        "Deleted the reply. The video is also posted elsewhere, so it was only \
+                         removed from your listing.",
+                    )
+                }
+            }
+            action => return Err(anyhow!("Unknown delete action {}", action)),
+        };
+
+        // Recorded before removing the reply so a changed video is never left unaudited
+        let entry = AuditEntry {
+            user_id,
+            user_name: &component.user.name,
+            action,
+            guild_id,
+            channel_id: Some(component.channel_id.get()),
+            message_id: Some(component.message.id.get()),
+            video_id: (action != "delete_reply").then_some(video_id),
+        };
+        if let Err(e) = audit::record(&entry).await {
+            error!("Failed to write audit entry: {:?}", e);
+        }
+        component.message.delete(&ctx.http).await?;
+
+        Ok(content.to_string())
+    }
+}
@@ -1,2 +1,9 @@
 pub mod convert;
+pub mod delete;
+pub mod settings;
 pub use convert::YliProxyHandler;
+pub use delete::DeleteButtons;
+pub use settings::SettingsCommand;
+
+// Video sites guilds can enable and disable
+pub const PROVIDERS: [&str; 1] = [convert::PROVIDER];
@@ -0,0 +1,456 @@
+use anyhow::{anyhow, Result};
+use serenity::all::{
+    ChannelType, CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, InteractionContext, Permissions,
+    ResolvedOption, ResolvedValue,
+};
+use serenity::prelude::*;
+use tracing::{error, info};
+
+use crate::bot::commands::PROVIDERS;
+use crate::config::CONFIG;
+use crate::settings::{GuildSettings, ReactionStyle, ReplyMode, SETTINGS};
+
+pub const COMMAND_NAME: &str = "cerebro";
+
+// Choice that falls back to the bot's configuration
+const DEFAULT_CHOICE: &str = "default";
+
+// Most choices Discord accepts for an option, it refuses the whole command beyond that
+const MAX_CHOICES: usize = 25;
+
+pub struct SettingsCommand;
+
+impl SettingsCommand {
+    pub fn register() -> CreateCommand {
+        let channel_option = || {
+            CreateCommandOption::new(CommandOptionType::Channel, "channel", "The channel")
+                .channel_types(vec![
+                    ChannelType::Text,
+                    ChannelType::News,
+                    ChannelType::Forum,
+                    ChannelType::PublicThread,
+                    ChannelType::PrivateThread,
+                ])
+                .required(true)
+        };
+
+        let provider_option = || {
+            let mut option =
+                CreateCommandOption::new(CommandOptionType::String, "name", "The video site")
+                    .required(true);
+            for provider in PROVIDERS {
+                option = option.add_string_choice(provider, provider);
+            }
+            option
+        };
+
+        CreateCommand::new(COMMAND_NAME)
+            .description("Configure the bot for this server")
+            .default_member_permissions(Permissions::MANAGE_GUILD)
+            .contexts(vec![InteractionContext::Guild])
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "status",
+                "Show the settings of this server",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "enable",
+                "Convert videos posted in this server",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "disable",
+                "Stop converting videos posted in this server",
+            ))
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommandGroup,
+                    "channel",
+                    "Choose the channels the bot listens in",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::SubCommand,
+                        "allow",
+                        "Listen in this channel, once a channel is allowed all others are ignored",
+                    )
+                    .add_sub_option(channel_option()),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::SubCommand,
+                        "deny",
+                        "Ignore this channel",
+                    )
+                    .add_sub_option(channel_option()),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::SubCommand,
+                        "reset",
+                        "Remove this channel from the allowed and ignored channels",
+                    )
+                    .add_sub_option(channel_option()),
+                ),
+            )
+            .add_option(Self::set_options())
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommandGroup,
+                    "provider",
+                    "Choose the video sites links are converted from",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::SubCommand,
+                        "enable",
+                        "Convert links to this site",
+                    )
+                    .add_sub_option(provider_option()),
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::SubCommand,
+                        "disable",
+                        "Ignore links to this site",
+                    )
+                    .add_sub_option(provider_option()),
+                ),
+            )
+    }
+
+    fn set_options() -> CreateCommandOption {
+        let setting = |name: &str, description: &str, value: CreateCommandOption| {
+            CreateCommandOption::new(CommandOptionType::SubCommand, name, description)
+                .add_sub_option(value.required(true))
+        };
+
+        let mut reactions =
+            CreateCommandOption::new(CommandOptionType::String, "style", "Reaction style");
+        for style in ReactionStyle::ALL {
+            reactions = reactions.add_string_choice(style.name(), style.name());
+        }
+
+        let mut replies =
+            CreateCommandOption::new(CommandOptionType::String, "mode", "Where to post")
+                .add_string_choice(DEFAULT_CHOICE, DEFAULT_CHOICE);
+        for mode in ReplyMode::ALL {
+            replies = replies.add_string_choice(mode.name(), mode.name());
+        }
+
+        // Too many profiles to pick from are typed in instead, names are checked when applied
+        let mut profile =
+            CreateCommandOption::new(CommandOptionType::String, "name", "Encoding profile");
+        if CONFIG.ffmpeg_profiles.len() < MAX_CHOICES {
+            profile = profile.add_string_choice(DEFAULT_CHOICE, DEFAULT_CHOICE);
+            let mut profiles: Vec<_> = CONFIG.ffmpeg_profiles.keys().collect();
+            profiles.sort();
+            for name in profiles {
+                profile = profile.add_string_choice(name, name);
+            }
+        }
+
+        CreateCommandOption::new(
+            CommandOptionType::SubCommandGroup,
+            "set",
+            "Change how the bot behaves in this server",
+        )
+        .add_sub_option(setting(
+            "reactions",
+            "Reactions added to messages with a link while converting",
+            reactions,
+        ))
+        .add_sub_option(setting(
+            "replies",
+            "Post videos in the channel, as a reply or in a thread on the message",
+            replies,
+        ))
+        .add_sub_option(setting(
+            "profile",
+            "Encoding profile used for new conversions",
+            profile,
+        ))
+        .add_sub_option(setting(
+            "suppress-embeds",
+            "Hide the unplayable embed of the original message",
+            CreateCommandOption::new(CommandOptionType::Boolean, "enabled", "Hide embeds"),
+        ))
+        .add_sub_option(setting(
+            "max-duration",
+            "Longest video to convert, 0 for the bot's limit",
+            CreateCommandOption::new(CommandOptionType::Integer, "seconds", "Duration in seconds")
+                .min_int_value(0),
+        ))
+    }
+
+    pub async fn handle(ctx: &Context, command: &CommandInteraction) {
+        let content = match Self::run(command).await {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Error running /{}: {:?}", COMMAND_NAME, e);
+                format!("Something went wrong: {}", e)
+            }
+        };
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(content)
+                .ephemeral(true),
+        );
+        if let Err(e) = command.create_response(&ctx.http, response).await {
+            error!("Error responding to /{}: {:?}", COMMAND_NAME, e);
+        }
+    }
+
+    async fn run(command: &CommandInteraction) -> Result<String> {
+        let Some(guild_id) = command.guild_id else {
+            return Ok("This command can only be used in a server.".to_string());
+        };
+        let guild_id = guild_id.get();
+
+        // Server permissions can override the command's default, so check again
+        let can_manage = command
+            .member
+            .as_ref()
+            .and_then(|member| member.permissions)
+            .is_some_and(|permissions| permissions.manage_guild());
+        if !can_manage && !CONFIG.admin_user_ids.contains(&command.user.id.get()) {
+            return Ok("You need the Manage Server permission to change settings.".to_string());
+        }
+
+        let options = command.data.options();
+        let Some(subcommand) = options.first() else {
+            return Err(anyhow!("No subcommand given"));
+        };
+
+        let (settings, change) = match (subcommand.name, &subcommand.value) {
+            ("status", _) => return Ok(Self::format_settings(&SETTINGS.get(guild_id).await)),
+            ("enable", _) => {
+                let settings = SETTINGS
+                    .update(guild_id, |settings| settings.disabled = false)
+                    .await?;
+                (settings, "enabled the bot".to_string())
+            }
+            ("disable", _) => {
+                let settings = SETTINGS
+                    .update(guild_id, |settings| settings.disabled = true)
+                    .await?;
+                (settings, "disabled the bot".to_string())
+            }
+            ("channel", ResolvedValue::SubCommandGroup(options)) => {
+                Self::update_channel(guild_id, options).await?
+            }
+            ("set", ResolvedValue::SubCommandGroup(options)) => {
+                Self::update_setting(guild_id, options).await?
+            }
+            ("provider", ResolvedValue::SubCommandGroup(options)) => {
+                Self::update_provider(guild_id, options).await?
+            }
+            (name, _) => return Err(anyhow!("Unknown subcommand {}", name)),
+        };
+
+        info!(
+            "{} ({}) {} in guild {}",
+            command.user.name, command.user.id, change, guild_id
+        );
+
+        Ok(Self::format_settings(&settings))
+    }
+
+    // Allow, deny or reset a channel, returning the new settings and what changed
+    async fn update_channel(
+        guild_id: u64,
+        options: &[ResolvedOption<'_>],
+    ) -> Result<(GuildSettings, String)> {
+        let Some(action) = options.first() else {
+            return Err(anyhow!("No channel action given"));
+        };
+        let ResolvedValue::SubCommand(arguments) = &action.value else {
+            return Err(anyhow!("Malformed channel action"));
+        };
+        let Some(channel_id) = arguments.iter().find_map(|argument| match argument.value {
+            ResolvedValue::Channel(channel) => Some(channel.id.get()),
+            _ => None,
+        }) else {
+            return Err(anyhow!("No channel given"));
+        };
+
+        let action = action.name;
+        let settings = SETTINGS
+            .update(guild_id, |settings| {
+                settings.allowed_channels.retain(|id| *id != channel_id);
+                settings.denied_channels.retain(|id| *id != channel_id);
+                match action {
+                    "allow" => settings.allowed_channels.push(channel_id),
+                    "deny" => settings.denied_channels.push(channel_id),
+                    _ => {}
+                }
+            })
+            .await?;
+
+        Ok((
+            settings,
+            format!("set channel {} to {}", channel_id, action),
+        ))
+    }
+
+    // Change a single setting, returning the new settings and what changed
+    async fn update_setting(
+        guild_id: u64,
+        options: &[ResolvedOption<'_>],
+    ) -> Result<(GuildSettings, String)> {
+        let Some(setting) = options.first() else {
+            return Err(anyhow!("No setting given"));
+        };
+        let ResolvedValue::SubCommand(arguments) = &setting.value else {
+            return Err(anyhow!("Malformed setting"));
+        };
+        let Some(value) = arguments.first().map(|argument| &argument.value) else {
+            return Err(anyhow!("No value given for {}", setting.name));
+        };
+
+        let change: Box<dyn FnOnce(&mut GuildSettings) + Send> = match (setting.name, value) {
+            ("reactions", ResolvedValue::String(style)) => {
+                let style = ReactionStyle::ALL
+                    .into_iter()
+                    .find(|known| known.name() == *style)
+                    .ok_or_else(|| anyhow!("Unknown reaction style {}", style))?;
+                Box::new(move |settings| settings.reactions = style)
+            }
+            ("replies", ResolvedValue::String(mode)) => {
+                let mode = match *mode {
+                    DEFAULT_CHOICE => None,
+                    mode => Some(
+                        ReplyMode::parse(mode)
+                            .ok_or_else(|| anyhow!("Unknown reply mode {}", mode))?,
+                    ),
+                };
+                Box::new(move |settings| settings.reply_mode = mode)
+            }
+            ("profile", ResolvedValue::String(name)) => {
+                let profile = match *name {
+                    DEFAULT_CHOICE => None,
+                    name if CONFIG.ffmpeg_profiles.contains_key(name) => Some(name.to_string()),
+                    name => return Err(anyhow!("Unknown encoding profile {}", name)),
+                };
+                Box::new(move |settings| settings.profile = profile)
+            }
+            ("suppress-embeds", ResolvedValue::Boolean(enabled)) => {
+                let enabled = *enabled;
+                Box::new(move |settings| settings.suppress_embeds = Some(enabled))
+            }
+            ("max-duration", ResolvedValue::Integer(seconds)) => {
+                let duration = (*seconds > 0).then_some(*seconds as f64);
+                Box::new(move |settings| settings.max_duration = duration)
+            }
+            (name, _) => return Err(anyhow!("Unknown setting {}", name)),
+        };
+
+        let description = format!("set {} to {}", setting.name, describe(value));
+        Ok((SETTINGS.update(guild_id, change).await?, description))
+    }
+
+    async fn update_provider(
+        guild_id: u64,
+        options: &[ResolvedOption<'_>],
+    ) -> Result<(GuildSettings, String)> {
+        let Some(action) = options.first() else {
+            return Err(anyhow!("No provider action given"));
+        };
+        let ResolvedValue::SubCommand(arguments) = &action.value else {
+            return Err(anyhow!("Malformed provider action"));
+        };
+        let Some(provider) = arguments.iter().find_map(|argument| match argument.value {
+            ResolvedValue::String(name) => PROVIDERS.iter().find(|provider| **provider == name),
+            _ => None,
+        }) else {
+            return Err(anyhow!("Unknown provider"));
+        };
+
+        let enable = action.name == "enable";
+        let settings = SETTINGS
+            .update(guild_id, |settings| {
+                settings.disabled_providers.retain(|name| name != provider);
+                if !enable {
+                    settings.disabled_providers.push(provider.to_string());
+                }
+            })
+            .await?;
+
+        Ok((settings, format!("{}d provider {}", action.name, provider)))
+    }
+
+    fn format_settings(settings: &GuildSettings) -> String {
+        let channels = |ids: &[u64], empty: &str| {
+            if ids.is_empty() {
+                empty.to_string()
+            } else {
+                ids.iter()
+                    .map(|id| format!("<#{}>", id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+
+        let providers: Vec<_> = PROVIDERS
+            .iter()
+            .filter(|provider| settings.provider_enabled(provider))
+            .copied()
+            .collect();
+        let max_duration = match settings.max_duration {
+            Some(_) => format!("{:.0} seconds", settings.max_duration()),
+            None => format!("{:.0} seconds (bot limit)", CONFIG.max_video_duration),
+        };
+
+        format!(
+            "**Converting videos:** {}\n\
+             **Allowed channels:** {}\n\
+             **Ignored channels:** {}\n\
+             **Reactions:** {}\n\
+             **Replies:** {}\n\
+             **Encoding profile:** {}\n\
+             **Suppress embeds:** {}\n\
+             **Max duration:** {}\n\
+             **Sites:** {}",
+            if settings.disabled { "no" } else { "yes" },
+            channels(&settings.allowed_channels, "all"),
+            channels(&settings.denied_channels, "none"),
+            settings.reactions.name(),
+            with_default(settings.reply_mode().name(), settings.reply_mode.is_none()),
+            settings.profile.as_deref().unwrap_or(DEFAULT_CHOICE),
+            with_default(
+                if settings.suppress_embeds() {
+                    "yes"
+                } else {
+                    "no"
+                },
+                settings.suppress_embeds.is_none()
+            ),
+            max_duration,
+            if providers.is_empty() {
+                "none".to_string()
+            } else {
+                providers.join(", ")
+            },
+        )
+    }
+}
+
+fn with_default(value: &str, is_default: bool) -> String {
+    if is_default {
+        format!("{} (bot default)", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn describe(value: &ResolvedValue) -> String {
+    match value {
+        ResolvedValue::String(value) => value.to_string(),
+        ResolvedValue::Boolean(value) => value.to_string(),
+        ResolvedValue::Integer(value) => value.to_string(),
+        _ => "?".to_string(),
+    }
+}
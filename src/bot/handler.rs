@@ -1,23 +1,73 @@
 use serenity::async_trait;
+use serenity::gateway::{ConnectionStage, ShardStageUpdateEvent};
+use serenity::model::application::{Command, Interaction};
 use serenity::model::channel::Message;
 use serenity::model::gateway::Ready;
 use serenity::prelude::*;
-use tracing::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{error, info, info_span, Instrument};
 
-use crate::bot::commands::YliProxyHandler;
+use crate::bot::access::is_allowed;
+use crate::bot::commands::delete::BUTTON_PREFIX;
+use crate::bot::commands::settings::COMMAND_NAME;
+use crate::bot::commands::{DeleteButtons, SettingsCommand, YliProxyHandler};
+use crate::health::HEALTH;
+
+// Slash commands only need registering once, not on every reconnect
+static COMMANDS_REGISTERED: AtomicBool = AtomicBool::new(false);
 
 pub struct Handler;
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
-        // Ignore messages from self and other bots
-        if !msg.author.bot {
-            YliProxyHandler::handle(&ctx, &msg).await;
+        // Ignore messages from self and other bots, and from where the bot isn't wanted
+        if !msg.author.bot && is_allowed(&ctx, &msg).await {
+            // Root of the trace for everything the message leads to
+            let span = info_span!(
+                "message",
+                message_id = msg.id.get(),
+                guild_id = msg.guild_id.map(|guild_id| guild_id.get()),
+                channel_id = msg.channel_id.get(),
+                user_id = msg.author.id.get()
+            );
+            YliProxyHandler::handle(&ctx, &msg).instrument(span).await;
         }
     }
 
-    async fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
+        HEALTH.set_gateway_connected(true);
+
+        if !COMMANDS_REGISTERED.swap(true, Ordering::SeqCst)
+            && let Err(e) =
+                Command::set_global_commands(&ctx.http, vec![SettingsCommand::register()]).await
+        {
+            // Try again on the next connection
+            COMMANDS_REGISTERED.store(false, Ordering::SeqCst);
+            error!("Failed to register slash commands: {:?}", e);
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::Command(command) if command.data.name == COMMAND_NAME => {
+                SettingsCommand::handle(&ctx, &command).await;
+            }
+            Interaction::Component(component)
+                if component
+                    .data
+                    .custom_id
+                    .starts_with(&format!("{}:", BUTTON_PREFIX)) =>
+            {
+                DeleteButtons::handle(&ctx, &component).await;
+            }
+            _ => {}
+        }
+    }
+
+    // Track the gateway connection for readiness checks
+    async fn shard_stage_update(&self, _: Context, event: ShardStageUpdateEvent) {
+        HEALTH.set_gateway_connected(event.new == ConnectionStage::Connected);
     }
 }
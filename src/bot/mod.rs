@@ -1,3 +1,4 @@
+pub mod access;
 pub mod client;
 pub mod commands;
 pub mod handler;
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use crate::bot::services::yliproxy::YliProxy;
+use crate::config::CONFIG;
+use crate::ffmpeg::run_ffmpeg;
+use crate::metadata::{lookup_hash, VideoMetadata};
+
+// Relative positions of the frames that make up the perceptual hash
+const HASH_POSITIONS: [f64; 3] = [0.25, 0.5, 0.75];
+
+// Videos whose durations differ more than this are never considered the same
+const DURATION_TOLERANCE: f64 = 1.0;
+
+// SHA-256 of a file as lowercase hex
+pub async fn content_hash(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Difference hash of a few frames spread over the video, as hex
+pub async fn perceptual_hash(path: &Path, duration: f64) -> Result<String> {
+    let mut hashes = Vec::new();
+
+    for position in HASH_POSITIONS {
+        // A 9x8 grayscale frame gives 8 horizontal gradients per row
+        let args = [
+            "-ss",
+            &format!("{:.3}", duration * position),
+            "-i",
+            path.to_str().unwrap(),
+            "-frames:v",
+            "1",
+            "-vf",
+            "scale=9:8:flags=area,format=gray",
+            "-f",
+            "rawvideo",
+            "-",
+        ];
+
+        let output = run_ffmpeg(&args, None).await?;
+        if !output.status.success() || output.stdout.len() < 72 {
+            return Err(anyhow::anyhow!(
+                "Failed to extract frame for perceptual hash"
+            ));
+        }
+
+        let mut hash: u64 = 0;
+        for row in output.stdout[..72].chunks(9) {
+            for pair in row.windows(2) {
+                hash = (hash << 1) | u64::from(pair[0] > pair[1]);
+            }
+        }
+        hashes.push(format!("{:016x}", hash));
+    }
+
+    Ok(hashes.join(""))
+}
+
+// Number of differing bits between two perceptual hashes
+fn distance(a: &str, b: &str) -> Option<u32> {
+    if a.len() != b.len() {
+        return None;
+    }
+
+    let mut total = 0;
+    for (a, b) in a.as_bytes().chunks(16).zip(b.as_bytes().chunks(16)) {
+        let a = u64::from_str_radix(std::str::from_utf8(a).ok()?, 16).ok()?;
+        let b = u64::from_str_radix(std::str::from_utf8(b).ok()?, 16).ok()?;
+        total += (a ^ b).count_ones();
+    }
+
+    Some(total)
+}
+
+// Find a stored video with identical content, or a perceptually similar one
+pub async fn find_duplicate(
+    content_hash: &str,
+    perceptual_hash: Option<&str>,
+    duration: Option<f64>,
+) -> Option<VideoMetadata> {
+    // Only videos whose converted file still exists can be reused
+    if let Some(id) = lookup_hash(content_hash).await
+        && let Some(metadata) = YliProxy::get_existing_metadata(&id).await
+    {
+        return Some(metadata);
+    }
+
+    let (perceptual_hash, duration) = (perceptual_hash?, duration?);
+    let videos = VideoMetadata::load_all().await.ok()?;
+
+    for video in videos {
+        let same_length = video
+            .duration
+            .is_some_and(|other| (other - duration).abs() <= DURATION_TOLERANCE);
+        let similar = video
+            .perceptual_hash
+            .as_deref()
+            .and_then(|other| distance(perceptual_hash, other))
+            .is_some_and(|distance| distance <= CONFIG.perceptual_dedup_threshold);
+
+        if same_length
+            && similar
+            && let Some(metadata) = YliProxy::get_existing_metadata(&video.id).await
+        {
+            return Some(metadata);
+        }
+    }
+
+    None
+}
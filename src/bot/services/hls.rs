@@ -0,0 +1,144 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tracing::info;
+
+use crate::bot::services::probe::{probe, MediaInfo};
+use crate::bot::services::renditions::ladder_for;
+use crate::config::CONFIG;
+use crate::ffmpeg::run_checked;
+use crate::storage::put_dir;
+
+// Segment length in seconds, keyframes are forced on segment boundaries
+const SEGMENT_LENGTH: u32 = 6;
+
+// Directory holding the HLS renditions of a video
+pub fn hls_dir(id: &str) -> PathBuf {
+    Path::new(&CONFIG.converted_dir).join(id).join("hls")
+}
+
+// Whether a video is long or heavy enough to be worth packaging as HLS
+fn should_package(info: &MediaInfo) -> bool {
+    if !CONFIG.hls_enabled {
+        return false;
+    }
+
+    let long = info
+        .duration
+        .is_some_and(|duration| duration >= CONFIG.hls_min_duration);
+    let heavy = info
+        .bit_rate
+        .is_some_and(|bit_rate| bit_rate >= CONFIG.hls_min_bitrate * 1000);
+
+    long || heavy
+}
+
+// Package a converted video as HLS if it qualifies, returning the master playlist
+pub async fn package_hls(video_path: &Path, id: &str) -> Result<Option<PathBuf>> {
+    let output_dir = hls_dir(id);
+    let master = output_dir.join("master.m3u8");
+
+    let info = probe(video_path).await?;
+    if !should_package(&info) {
+        return Ok(None);
+    }
+
+    // Write into a temporary directory so the playlist never appears half-done
+    let temp_dir = Path::new(&CONFIG.converted_dir).join(id).join("hls-tmp");
+    if fs::metadata(&temp_dir).await.is_ok() {
+        fs::remove_dir_all(&temp_dir).await?;
+    }
+    fs::create_dir_all(&temp_dir).await?;
+
+    let ladder = ladder_for(info.height);
+    let mut filter = format!("[0:v]split={}", ladder.len());
+    for i in 0..ladder.len() {
+        filter.push_str(&format!("[s{}]", i));
+    }
+    for (i, rendition) in ladder.iter().enumerate() {
+        filter.push_str(&format!(";[s{}]scale=-2:{}[v{}]", i, rendition.height, i));
+    }
+
+    let mut args: Vec<String> = vec![
+        "-y".into(),
+        "-i".into(),
+        video_path.to_str().unwrap().into(),
+        "-filter_complex".into(),
+        filter,
+    ];
+
+    let mut stream_map = Vec::new();
+    for (i, rendition) in ladder.iter().enumerate() {
+        args.extend([
+            "-map".into(),
+            format!("[v{}]", i),
+            format!("-c:v:{}", i),
+            "libx264".into(),
+            format!("-b:v:{}", i),
+            format!("{}k", rendition.video_bitrate),
+            format!("-maxrate:v:{}", i),
+            format!("{}k", rendition.video_bitrate * 107 / 100),
+            format!("-bufsize:v:{}", i),
+            format!("{}k", rendition.video_bitrate * 3 / 2),
+        ]);
+
+        if info.has_audio {
+            args.extend([
+                "-map".into(),
+                "a:0".into(),
+                format!("-c:a:{}", i),
+                "aac".into(),
+                format!("-b:a:{}", i),
+                format!("{}k", rendition.audio_bitrate),
+            ]);
+            stream_map.push(format!("v:{},a:{},name:{}", i, i, rendition.name));
+        } else {
+            stream_map.push(format!("v:{},name:{}", i, rendition.name));
+        }
+    }
+
+    args.extend([
+        "-preset".into(),
+        "veryfast".into(),
+        "-force_key_frames".into(),
+        format!("expr:gte(t,n_forced*{})", SEGMENT_LENGTH),
+        "-f".into(),
+        "hls".into(),
+        "-hls_time".into(),
+        SEGMENT_LENGTH.to_string(),
+        "-hls_playlist_type".into(),
+        "vod".into(),
+        "-hls_segment_filename".into(),
+        temp_dir
+            .join("%v")
+            .join("segment%03d.ts")
+            .to_str()
+            .unwrap()
+            .into(),
+        "-master_pl_name".into(),
+        "master.m3u8".into(),
+        "-var_stream_map".into(),
+        stream_map.join(" "),
+        temp_dir
+            .join("%v")
+            .join("index.m3u8")
+            .to_str()
+            .unwrap()
+            .into(),
+    ]);
+
+    if let Err(e) = run_checked(&args, Some(id), "package HLS").await {
+        fs::remove_dir_all(&temp_dir).await.ok();
+        return Err(e.into());
+    }
+
+    // Swap in the new packaging, replacing the one from an earlier run
+    if fs::metadata(&output_dir).await.is_ok() {
+        fs::remove_dir_all(&output_dir).await?;
+    }
+    fs::rename(&temp_dir, &output_dir).await?;
+    put_dir(&output_dir).await?;
+    info!("Packaged {} as HLS with {} renditions", id, ladder.len());
+
+    Ok(Some(master))
+}
@@ -1 +1,7 @@
+pub mod dedup;
+pub mod hls;
+pub mod pipeline;
+pub mod probe;
+pub mod renditions;
+pub mod upload;
 pub mod yliproxy;
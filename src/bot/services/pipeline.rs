@@ -0,0 +1,211 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::{error, info, info_span, Instrument};
+
+use crate::bot::services::dedup::{content_hash, find_duplicate, perceptual_hash};
+use crate::bot::services::hls::package_hls;
+use crate::bot::services::probe::Rejection;
+use crate::bot::services::renditions::add_renditions;
+use crate::bot::services::yliproxy::YliProxy;
+use crate::config::CONFIG;
+use crate::jobs::{Origin, Outcome, OutcomeReceiver, JOBS};
+use crate::metadata::{record_alias, VideoMetadata};
+use crate::metrics::{
+    failure_reason, ENCODE_DURATION, ENCODE_SPEED, JOBS_FAILED, JOBS_QUEUED, JOBS_SUCCEEDED,
+};
+use crate::settings::SETTINGS;
+use crate::storage::release_local;
+use crate::telemetry::current_traceparent;
+
+// Download, validate and convert a video as a tracked job, returning the stored video
+// which may be an existing one the download turned out to duplicate
+pub async fn run_job(url: &str, id: &str, origin: Origin) -> Result<VideoMetadata> {
+    // Every log line of the job carries where it came from
+    let span = info_span!(
+        "job",
+        id,
+        source_url = url,
+        guild_id = origin.guild_id,
+        channel_id = origin.channel_id,
+        user_id = origin.submitter_id
+    );
+    track_job(url, id, origin).instrument(span).await
+}
+
+async fn track_job(url: &str, id: &str, origin: Origin) -> Result<VideoMetadata> {
+    if let Err(running) = JOBS.start(id, url, origin.clone()).await {
+        return join(id, &origin, running).await;
+    }
+
+    // Children are killed if the job runs out of time
+    JOBS_QUEUED.inc();
+    let result = match tokio::time::timeout(CONFIG.job_timeout, convert(url, id, &origin)).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!(
+            "Conversion of {} timed out after {:?}",
+            id,
+            CONFIG.job_timeout
+        )),
+    };
+
+    let outcome = match &result {
+        Ok((metadata, _)) => Outcome::Converted(metadata.id.clone()),
+        Err(e) => match e.downcast_ref::<Rejection>() {
+            Some(rejection) => Outcome::Rejected(rejection.0.clone()),
+            None => Outcome::Failed(format!("{:#}", e)),
+        },
+    };
+    if let Some(job) = JOBS.finish(id, outcome).await {
+        info!(
+            "Job {} finished in {:?} using {:?} of CPU time",
+            job.id,
+            job.started_at.elapsed().unwrap_or_default(),
+            job.cpu_time
+        );
+    }
+
+    let (metadata, output_file) = match result {
+        Ok(converted) => converted,
+        Err(e) => {
+            JOBS_FAILED.with_label_values(&[failure_reason(&e)]).inc();
+            JOBS.record_failure(id, url, origin, &e).await;
+            return Err(e);
+        }
+    };
+    JOBS_SUCCEEDED.inc();
+
+    // Derived files are built in the background, the MP4 link works meanwhile
+    if let Some(output_file) = output_file {
+        let id = id.to_string();
+        tokio::spawn(async move { build_derived(&output_file, &id).await }.in_current_span());
+    }
+
+    Ok(metadata)
+}
+
+// Encode the other renditions and package for adaptive streaming from the primary file,
+// then drop the local copies kept for it
+pub async fn build_derived(primary: &Path, id: &str) {
+    if let Err(e) = add_renditions(primary, id)
+        .instrument(info_span!("renditions"))
+        .await
+    {
+        error!("Failed to generate renditions for {}: {:?}", id, e);
+    }
+
+    async {
+        match package_hls(primary, id).await {
+            Ok(Some(_)) => {
+                if let Err(e) = VideoMetadata::update(id, |metadata| metadata.hls = true).await {
+                    error!("Failed to record HLS for {}: {:?}", id, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to package HLS for {}: {:?}", id, e),
+        }
+    }
+    .instrument(info_span!("hls"))
+    .await;
+
+    release_local(id).await;
+}
+
+// Wait for the job already converting the video and record this request as another posting
+async fn join(id: &str, origin: &Origin, mut running: OutcomeReceiver) -> Result<VideoMetadata> {
+    info!("{} is already being converted, waiting for it", id);
+    let outcome = running
+        .wait_for(Option::is_some)
+        .await
+        .ok()
+        .and_then(|outcome| outcome.clone());
+
+    match outcome {
+        Some(Outcome::Converted(converted_id)) => {
+            // Background work may have updated it since, or it may have been deleted
+            VideoMetadata::update(&converted_id, |metadata| {
+                metadata.add_posting(origin);
+            })
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("{} was deleted after converting", converted_id))
+        }
+        Some(Outcome::Rejected(reason)) => Err(Rejection(reason).into()),
+        Some(Outcome::Failed(error)) => Err(anyhow::anyhow!(error)),
+        None => Err(anyhow::anyhow!("The conversion of {} was abandoned", id)),
+    }
+}
+
+// Convert a new video, the output file is None if an existing conversion was reused
+async fn convert(url: &str, id: &str, origin: &Origin) -> Result<(VideoMetadata, Option<PathBuf>)> {
+    // Guilds can pick their encoding profile and lower the duration limit
+    let settings = SETTINGS.for_guild(origin.guild_id).await;
+
+    let file_path = YliProxy::download_file(url)
+        .instrument(info_span!("download"))
+        .await?;
+    let info = YliProxy::validate_download(&file_path, settings.max_duration())
+        .instrument(info_span!("probe"))
+        .await?;
+
+    let content_hash = content_hash(&file_path).await?;
+    let perceptual_hash = match info.duration {
+        Some(duration) if CONFIG.perceptual_dedup => {
+            match perceptual_hash(&file_path, duration).await {
+                Ok(hash) => Some(hash),
+                Err(e) => {
+                    error!("Failed to compute perceptual hash for {}: {:?}", id, e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    if let Some(existing) =
+        find_duplicate(&content_hash, perceptual_hash.as_deref(), info.duration).await
+    {
+        info!(
+            "{} is a duplicate of {}, reusing its conversion",
+            id, existing.id
+        );
+        YliProxy::remove_download(&file_path).await;
+
+        // The duplicate is listed wherever it was posted, as privately as its strictest posting
+        record_alias(id, &existing.id).await?;
+        let existing = VideoMetadata::update(&existing.id, |existing| {
+            existing.add_posting(origin);
+            if !existing.aliases.iter().any(|alias| alias == id) {
+                existing.aliases.push(id.to_string());
+            }
+        })
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("{} was deleted while deduplicating", existing.id))?;
+
+        return Ok((existing, None));
+    }
+
+    let encode_started = Instant::now();
+    let output_file = YliProxy::convert_to_h264(&file_path, id, settings.ffmpeg_args())
+        .instrument(info_span!("convert"))
+        .await?;
+    let encode_time = encode_started.elapsed().as_secs_f64();
+    ENCODE_DURATION.observe(encode_time);
+    if let Some(duration) = info.duration
+        && encode_time > 0.0
+    {
+        ENCODE_SPEED.observe(duration / encode_time);
+    }
+
+    let mut metadata = VideoMetadata::new(id);
+    metadata.source_url = Some(url.to_string());
+    metadata.duration = info.duration;
+    metadata.content_hash = Some(content_hash);
+    metadata.perceptual_hash = perceptual_hash;
+    metadata.private = origin.private;
+    metadata.guild_id = origin.guild_id;
+    metadata.submitter_id = origin.submitter_id;
+    metadata.traceparent = current_traceparent();
+    let metadata = YliProxy::store_metadata(&output_file, metadata).await?;
+
+    Ok((metadata, Some(output_file)))
+}
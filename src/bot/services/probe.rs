@@ -0,0 +1,171 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::config::CONFIG;
+use crate::ffmpeg;
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+}
+
+// Summary of a media file as reported by ffprobe
+pub struct MediaInfo {
+    pub format_name: Option<String>,
+    pub duration: Option<f64>,
+    pub bit_rate: Option<u64>,
+    pub has_video: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    pub has_audio: bool,
+}
+
+// Reason a file was refused, shown to the requester as-is
+#[derive(Debug)]
+pub struct Rejection(pub String);
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+pub async fn probe(path: &Path) -> Result<MediaInfo> {
+    let mut command = ffmpeg::command(&CONFIG.ffprobe_bin);
+    command.args([
+        "-v",
+        "error",
+        "-print_format",
+        "json",
+        "-show_format",
+        "-show_streams",
+        path.to_str().unwrap(),
+    ]);
+    let output = ffmpeg::output(command).await?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("Failed to probe media: {}", error));
+    }
+
+    let parsed: ProbeOutput = serde_json::from_slice(&output.stdout)?;
+    let video = parsed
+        .streams
+        .iter()
+        .find(|stream| stream.codec_type.as_deref() == Some("video"));
+    let format = parsed.format.as_ref();
+
+    Ok(MediaInfo {
+        format_name: format.and_then(|format| format.format_name.clone()),
+        duration: format
+            .and_then(|format| format.duration.as_ref())
+            .and_then(|duration| duration.parse().ok()),
+        bit_rate: format
+            .and_then(|format| format.bit_rate.as_ref())
+            .and_then(|bit_rate| bit_rate.parse().ok()),
+        has_video: video.is_some(),
+        width: video.and_then(|stream| stream.width),
+        height: video.and_then(|stream| stream.height),
+        frame_rate: video.and_then(|stream| {
+            // Streams without a known average report 0/0
+            parse_rate(stream.avg_frame_rate.as_deref())
+                .or_else(|| parse_rate(stream.r_frame_rate.as_deref()))
+        }),
+        has_audio: parsed
+            .streams
+            .iter()
+            .any(|stream| stream.codec_type.as_deref() == Some("audio")),
+    })
+}
+
+// Parse an ffprobe rational such as 30000/1001
+fn parse_rate(rate: Option<&str>) -> Option<f64> {
+    let (numerator, denominator) = rate?.split_once('/')?;
+    let numerator: f64 = numerator.parse().ok()?;
+    let denominator: f64 = denominator.parse().ok()?;
+
+    if numerator > 0.0 && denominator > 0.0 {
+        Some(numerator / denominator)
+    } else {
+        None
+    }
+}
+
+// Reject videos longer than the limit
+pub fn check_duration(duration: f64, max_duration: f64) -> Result<(), Rejection> {
+    if duration > max_duration {
+        return Err(Rejection(format!(
+            "the video is {:.0} seconds long, the limit is {:.0} seconds",
+            duration, max_duration
+        )));
+    }
+    Ok(())
+}
+
+// Check a probed file against the configured input limits
+pub fn validate(info: &MediaInfo, max_duration: f64) -> Result<(), Rejection> {
+    let format_name = info.format_name.as_deref().unwrap_or_default();
+    let allowed = format_name.split(',').any(|format| {
+        CONFIG
+            .allowed_containers
+            .iter()
+            .any(|allowed| allowed == format)
+    });
+    if !allowed {
+        return Err(Rejection(format!(
+            "the container format '{}' is not allowed",
+            format_name
+        )));
+    }
+
+    if !info.has_video {
+        return Err(Rejection("the file has no video stream".to_string()));
+    }
+
+    match info.duration {
+        Some(duration) => check_duration(duration, max_duration)?,
+        None => return Err(Rejection("the video duration is unknown".to_string())),
+    }
+
+    if let (Some(width), Some(height)) = (info.width, info.height)
+        && (width > CONFIG.max_video_width || height > CONFIG.max_video_height)
+    {
+        return Err(Rejection(format!(
+            "the resolution {}x{} exceeds the limit of {}x{}",
+            width, height, CONFIG.max_video_width, CONFIG.max_video_height
+        )));
+    }
+
+    if let Some(frame_rate) = info.frame_rate
+        && frame_rate > CONFIG.max_video_frame_rate
+    {
+        return Err(Rejection(format!(
+            "the frame rate {:.1} fps exceeds the limit of {:.0} fps",
+            frame_rate, CONFIG.max_video_frame_rate
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use anyhow::Result;
+use std::path::Path;
+use tokio::fs;
+use tracing::{error, info};
+
+use crate::bot::services::probe::{probe, MediaInfo};
+use crate::config::CONFIG;
+use crate::ffmpeg::{run_checked, write_atomically};
+use crate::metadata::{Rendition, VideoMetadata};
+use crate::storage::STORAGE;
+
+// A single step of the encoding ladder
+pub struct RenditionSpec {
+    pub name: &'static str,
+    pub height: u32,
+    // Bitrates in kbit/s
+    pub video_bitrate: u32,
+    pub audio_bitrate: u32,
+}
+
+// Encoding ladder, highest quality first
+pub const LADDER: [RenditionSpec; 3] = [
+    RenditionSpec {
+        name: "1080p",
+        height: 1080,
+        video_bitrate: 5000,
+        audio_bitrate: 160,
+    },
+    RenditionSpec {
+        name: "720p",
+        height: 720,
+        video_bitrate: 2800,
+        audio_bitrate: 128,
+    },
+    RenditionSpec {
+        name: "480p",
+        height: 480,
+        video_bitrate: 1200,
+        audio_bitrate: 96,
+    },
+];
+
+// Renditions that don't upscale the source, always at least the smallest one
+pub fn ladder_for(source_height: Option<u32>) -> Vec<&'static RenditionSpec> {
+    let Some(source_height) = source_height else {
+        return LADDER.iter().collect();
+    };
+
+    let ladder: Vec<_> = LADDER
+        .iter()
+        .filter(|rendition| rendition.height <= source_height)
+        .collect();
+
+    if ladder.is_empty() {
+        vec![&LADDER[LADDER.len() - 1]]
+    } else {
+        ladder
+    }
+}
+
+// Encode the ladder steps below the primary file's height plus an audio-only track
+pub async fn generate_renditions(
+    primary: &Path,
+    id: &str,
+    info: &MediaInfo,
+) -> Result<Vec<Rendition>> {
+    let output_dir = Path::new(&CONFIG.converted_dir).join(id);
+    fs::create_dir_all(&output_dir).await?;
+
+    let mut renditions = Vec::new();
+
+    let source_height = info.height.unwrap_or(0);
+    for spec in LADDER.iter().filter(|spec| spec.height < source_height) {
+        let file_name = format!("{}/{}.mp4", id, spec.name);
+        let output = Path::new(&CONFIG.converted_dir).join(&file_name);
+
+        write_atomically(&output, |temp_path| async move {
+            run_checked(
+                &[
+                    "-y",
+                    "-i",
+                    primary.to_str().unwrap(),
+                    "-vf",
+                    &format!("scale=-2:{}", spec.height),
+                    "-c:v",
+                    "libx264",
+                    "-preset",
+                    "veryfast",
+                    "-b:v",
+                    &format!("{}k", spec.video_bitrate),
+                    "-maxrate",
+                    &format!("{}k", spec.video_bitrate * 107 / 100),
+                    "-bufsize",
+                    &format!("{}k", spec.video_bitrate * 3 / 2),
+                    "-c:a",
+                    "aac",
+                    "-b:a",
+                    &format!("{}k", spec.audio_bitrate),
+                    "-movflags",
+                    "+faststart",
+                    temp_path.to_str().unwrap(),
+                ],
+                Some(id),
+                "encode rendition",
+            )
+            .await
+        })
+        .await?;
+
+        renditions.push(Rendition {
+            name: spec.name.to_string(),
+            file_name,
+            height: Some(spec.height),
+            audio_only: false,
+        });
+    }
+
+    if info.has_audio {
+        let file_name = format!("{}/audio.m4a", id);
+        let output = Path::new(&CONFIG.converted_dir).join(&file_name);
+
+        write_atomically(&output, |temp_path| async move {
+            run_checked(
+                &[
+                    "-y",
+                    "-i",
+                    primary.to_str().unwrap(),
+                    "-vn",
+                    "-c:a",
+                    "aac",
+                    "-b:a",
+                    "128k",
+                    "-movflags",
+                    "+faststart",
+                    temp_path.to_str().unwrap(),
+                ],
+                Some(id),
+                "encode rendition",
+            )
+            .await
+        })
+        .await?;
+
+        renditions.push(Rendition {
+            name: "audio".to_string(),
+            file_name,
+            height: None,
+            audio_only: true,
+        });
+    }
+
+    info!("Generated {} renditions for {}", renditions.len(), id);
+    Ok(renditions)
+}
+
+// Encode and publish the extra renditions of a stored video, then add them to its metadata
+pub async fn add_renditions(primary: &Path, id: &str) -> Result<()> {
+    if !CONFIG.renditions_enabled {
+        return Ok(());
+    }
+
+    let info = probe(primary).await?;
+    let renditions = generate_renditions(primary, id, &info).await?;
+
+    let mut published = Vec::new();
+    for rendition in &renditions {
+        let path = Path::new(&CONFIG.converted_dir).join(&rendition.file_name);
+        if let Err(e) = STORAGE.put(&rendition.file_name, &path).await {
+            // Don't leave a partial set of renditions behind
+            unpublish(&published).await;
+            return Err(e);
+        }
+        published.push(rendition.file_name.clone());
+    }
+
+    // Replace the renditions of an earlier run, the primary comes first
+    let updated = VideoMetadata::update(id, |metadata| {
+        metadata.renditions.truncate(1);
+        metadata.renditions.extend(renditions);
+    })
+    .await?;
+
+    // The video may have been deleted while encoding
+    if updated.is_none() {
+        unpublish(&published).await;
+    }
+    Ok(())
+}
+
+async fn unpublish(keys: &[String]) {
+    for key in keys {
+        if let Err(e) = STORAGE.delete(key).await {
+            error!("Failed to remove {} from storage: {:?}", key, e);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::Result;
+use serenity::model::channel::Message;
+use serenity::model::guild::PremiumTier;
+use serenity::prelude::*;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tracing::{error, info};
+
+use crate::bot::services::probe::probe;
+use crate::config::CONFIG;
+use crate::ffmpeg::run_checked;
+use crate::metadata::VideoMetadata;
+use crate::storage::STORAGE;
+
+const MIB: u64 = 1024 * 1024;
+
+// Upload limit for servers without boosts and for DMs
+const DEFAULT_UPLOAD_LIMIT: u64 = 10 * MIB;
+
+// Headroom left for the container overhead when targeting a file size
+const SIZE_MARGIN: f64 = 0.95;
+
+// Below this the re-encoded video isn't worth watching
+const MIN_VIDEO_BITRATE: u64 = 150;
+
+// Audio bitrate of re-encoded uploads in kbit/s
+const AUDIO_BITRATE: u64 = 96;
+
+// A file ready to be attached to a reply
+pub struct PreparedUpload {
+    pub path: PathBuf,
+    // Re-encoded files are removed once uploaded
+    temporary: bool,
+}
+
+impl PreparedUpload {
+    pub async fn cleanup(self) {
+        if self.temporary
+            && let Err(e) = fs::remove_file(&self.path).await
+        {
+            error!("Failed to remove upload {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// Attachment size limit in bytes for the channel a message was posted in
+pub async fn upload_limit(ctx: &Context, msg: &Message) -> u64 {
+    let Some(guild_id) = msg.guild_id else {
+        return DEFAULT_UPLOAD_LIMIT;
+    };
+
+    match guild_id.to_partial_guild(&ctx.http).await {
+        Ok(guild) => match guild.premium_tier {
+            PremiumTier::Tier2 => 50 * MIB,
+            PremiumTier::Tier3 => 100 * MIB,
+            _ => DEFAULT_UPLOAD_LIMIT,
+        },
+        Err(e) => {
+            error!(
+                "Failed to fetch guild {} for upload limit: {:?}",
+                guild_id, e
+            );
+            DEFAULT_UPLOAD_LIMIT
+        }
+    }
+}
+
+// Find or produce a file for the video that fits under the upload limit
+pub async fn prepare_upload(
+    metadata: &VideoMetadata,
+    limit: u64,
+) -> Result<Option<PreparedUpload>> {
+    // Existing renditions are tried from the best quality down
+    for rendition in metadata.video_renditions() {
+        let path = Path::new(&CONFIG.converted_dir).join(&rendition.file_name);
+        if let Ok(file) = fs::metadata(&path).await
+            && file.len() <= limit
+        {
+            return Ok(Some(PreparedUpload {
+                path,
+                temporary: false,
+            }));
+        }
+    }
+
+    if !CONFIG.discord_upload_reencode {
+        return Ok(None);
+    }
+
+    // Videos kept in remote storage are fetched for the re-encode
+    let mut primary = Path::new(&CONFIG.converted_dir).join(format!("{}.mp4", metadata.id));
+    let mut fetched = None;
+    if fs::metadata(&primary).await.is_err() {
+        let path = work_path(&metadata.id, "source")?;
+        STORAGE
+            .fetch(&format!("{}.mp4", metadata.id), &path)
+            .await?;
+        primary = path.clone();
+        fetched = Some(path);
+    }
+
+    let result = reencode_to_fit(metadata, &primary, limit).await;
+    if let Some(path) = fetched {
+        fs::remove_file(path).await.ok();
+    }
+    result
+}
+
+// Working file in the download directory, unique so replies for the same video at the
+// same time don't overwrite each other's files
+fn work_path(id: &str, kind: &str) -> Result<PathBuf> {
+    let mut bytes = [0u8; 8];
+    getrandom::fill(&mut bytes)?;
+    let suffix: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(Path::new(&CONFIG.download_dir).join(format!("{}-{}-{}.mp4", id, kind, suffix)))
+}
+
+// Re-encode the video with a target bitrate that lands it under the limit
+async fn reencode_to_fit(
+    metadata: &VideoMetadata,
+    primary: &Path,
+    limit: u64,
+) -> Result<Option<PreparedUpload>> {
+    let Some(duration) = probe(primary).await?.duration else {
+        return Ok(None);
+    };
+
+    // Total bitrate budget in kbit/s for the file to land under the limit
+    let total_bitrate = (limit as f64 * 8.0 * SIZE_MARGIN / duration / 1000.0) as u64;
+    let video_bitrate = total_bitrate.saturating_sub(AUDIO_BITRATE);
+    if video_bitrate < MIN_VIDEO_BITRATE {
+        info!(
+            "{} is too long to fit in {} bytes at a watchable bitrate",
+            metadata.id, limit
+        );
+        return Ok(None);
+    }
+
+    let output = work_path(&metadata.id, "upload")?;
+    // Unique names would leave failed attempts behind forever
+    if let Err(e) = two_pass_encode(&metadata.id, primary, &output, video_bitrate).await {
+        fs::remove_file(&output).await.ok();
+        return Err(e);
+    }
+
+    // Two-pass encodes can still overshoot slightly on very short clips
+    if fs::metadata(&output).await?.len() > limit {
+        fs::remove_file(&output).await.ok();
+        return Ok(None);
+    }
+
+    Ok(Some(PreparedUpload {
+        path: output,
+        temporary: true,
+    }))
+}
+
+async fn two_pass_encode(id: &str, input: &Path, output: &Path, video_bitrate: u64) -> Result<()> {
+    let passlog = output.with_extension("passlog");
+    let input = input.to_str().unwrap();
+    let passlog_prefix = passlog.to_str().unwrap();
+    let bitrate = format!("{}k", video_bitrate);
+    let audio_bitrate = format!("{}k", AUDIO_BITRATE);
+
+    let passes: [Vec<&str>; 2] = [
+        vec!["-pass", "1", "-an", "-f", "null", "/dev/null"],
+        vec![
+            "-pass",
+            "2",
+            "-c:a",
+            "aac",
+            "-b:a",
+            &audio_bitrate,
+            "-movflags",
+            "+faststart",
+            output.to_str().unwrap(),
+        ],
+    ];
+
+    let mut result = Ok(());
+    for pass in passes {
+        let args: Vec<&str> = [
+            "-y",
+            "-i",
+            input,
+            "-c:v",
+            "libx264",
+            "-preset",
+            "medium",
+            "-b:v",
+            &bitrate,
+            "-passlogfile",
+            passlog_prefix,
+        ]
+        .into_iter()
+        .chain(pass)
+        .collect();
+
+        if let Err(e) = run_checked(&args, Some(id), "re-encode for upload").await {
+            result = Err(e.into());
+            break;
+        }
+    }
+
+    // x264 leaves its statistics next to the prefix
+    for suffix in ["-0.log", "-0.log.mbtree"] {
+        fs::remove_file(format!("{}{}", passlog_prefix, suffix))
+            .await
+            .ok();
+    }
+
+    result
+}
@@ -1,12 +1,17 @@
 use anyhow::Result;
-use async_process::Command;
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::path::{Path, PathBuf};
 use tokio::fs;
 use tracing::{error, info};
 
+use crate::bot::services::probe::{probe, validate, MediaInfo, Rejection};
 use crate::config::CONFIG;
+use crate::ffmpeg::run_checked;
+use crate::metadata::{record_hash, Rendition, VideoMetadata};
+use crate::metrics::{DOWNLOAD_BYTES, DOWNLOAD_DURATION};
+use crate::signing::sign_url;
+use crate::storage::STORAGE;
 
 lazy_static! {
     static ref ID_PATTERN: Regex = Regex::new(r"/([^/]+)\.mp4$").unwrap();
@@ -15,35 +20,30 @@ lazy_static! {
 pub struct YliProxy;
 
 impl YliProxy {
-    pub async fn convert_to_h264(input_path: &Path, id: &str) -> Result<PathBuf> {
+    pub async fn convert_to_h264(
+        input_path: &Path,
+        id: &str,
+        ffmpeg_args: &str,
+    ) -> Result<PathBuf> {
         let file_name = format!("{}.mp4", id);
         let output_file = Path::new(&CONFIG.converted_dir).join(&file_name);
-        let ffmpeg_args = CONFIG
-            .ffmpeg_args
+        let ffmpeg_args = ffmpeg_args
             .replace("$INPUT", input_path.to_str().unwrap())
             .replace("$OUTPUT", output_file.to_str().unwrap());
         let ffmpeg_args: Vec<&str> = ffmpeg_args.split_whitespace().collect();
 
-        let output = Command::new(&CONFIG.ffmpeg_bin)
-            .args(ffmpeg_args)
-            .output()
-            .await?;
+        let result = run_checked(&ffmpeg_args, Some(id), "convert video").await;
 
         // Cleanup the downloaded file
-        if let Err(e) = fs::remove_file(input_path).await {
-            error!("Failed to remove temp file {}: {}", input_path.display(), e);
-        }
+        Self::remove_download(input_path).await;
 
-        if output.status.success() {
-            info!("Successfully converted video to H264: {}", file_name);
-            Ok(output_file)
-        } else {
-            let error = String::from_utf8_lossy(&output.stderr);
-            Err(anyhow::anyhow!("Failed to convert video: {}", error))
-        }
+        result?;
+        info!("Successfully converted video to H264: {}", file_name);
+        Ok(output_file)
     }
 
     pub async fn download_file(url: &str) -> Result<PathBuf> {
+        let timer = DOWNLOAD_DURATION.start_timer();
         let res = reqwest::get(url).await?;
 
         if res.status().is_success() {
@@ -56,6 +56,8 @@ impl YliProxy {
             let mut dest = fs::File::create(&file_path).await?;
 
             let content = res.bytes().await?;
+            timer.observe_duration();
+            DOWNLOAD_BYTES.inc_by(content.len() as u64);
             tokio::io::copy(&mut content.as_ref(), &mut dest).await?;
             info!(
                 "File '{}' downloaded and saved successfully.",
@@ -70,6 +72,31 @@ impl YliProxy {
         }
     }
 
+    // Probe a downloaded file and refuse it if it's not a video within the limits
+    pub async fn validate_download(file_path: &Path, max_duration: f64) -> Result<MediaInfo> {
+        let result = match probe(file_path).await {
+            Ok(info) => validate(&info, max_duration)
+                .map(|()| info)
+                .map_err(anyhow::Error::from),
+            Err(e) => {
+                error!("Failed to probe {}: {:?}", file_path.display(), e);
+                Err(Rejection("the file is not a recognizable video".to_string()).into())
+            }
+        };
+
+        if result.is_err() {
+            Self::remove_download(file_path).await;
+        }
+
+        result
+    }
+
+    pub async fn remove_download(file_path: &Path) {
+        if let Err(e) = fs::remove_file(file_path).await {
+            error!("Failed to remove temp file {}: {}", file_path.display(), e);
+        }
+    }
+
     pub fn extract_id_from_url(url: &str) -> Result<String> {
         ID_PATTERN
             .captures(url)
@@ -78,18 +105,79 @@ impl YliProxy {
             .ok_or_else(|| anyhow::anyhow!("Failed to extract ID from URL: {}", url))
     }
 
-    pub async fn get_existing_file_url(id: &str) -> Option<String> {
+    pub async fn get_existing_metadata(id: &str) -> Option<VideoMetadata> {
+        // Metadata is only saved once the files are published, which may be remotely
+        if let Some(metadata) = VideoMetadata::load(id).await {
+            return Some(metadata);
+        }
+
+        // Files converted before metadata was tracked only have the primary rendition
         let file_name = format!("{}.mp4", id);
         let output_path = Path::new(&CONFIG.converted_dir).join(&file_name);
+        if fs::metadata(&output_path).await.is_err() {
+            return None;
+        }
 
-        if fs::metadata(&output_path).await.is_ok() {
-            Some(Self::get_file_url(&file_name))
-        } else {
-            None
+        let mut metadata = VideoMetadata::new(id);
+        metadata.renditions.push(Rendition {
+            name: "source".to_string(),
+            file_name,
+            height: None,
+            audio_only: false,
+        });
+        Some(metadata)
+    }
+
+    // Record the converted file in the video's metadata, extra renditions are added later
+    pub async fn store_metadata(
+        output_file: &Path,
+        mut metadata: VideoMetadata,
+    ) -> Result<VideoMetadata> {
+        let id = metadata.id.clone();
+        let id = id.as_str();
+
+        let info = probe(output_file).await;
+        if let Err(e) = &info {
+            error!("Failed to probe converted file {}: {:?}", id, e);
+        }
+
+        metadata.renditions.push(Rendition {
+            name: "source".to_string(),
+            file_name: format!("{}.mp4", id),
+            height: info.as_ref().ok().and_then(|info| info.height),
+            audio_only: false,
+        });
+
+        // Publish the video before anyone gets a link to it
+        STORAGE.put(&format!("{}.mp4", id), output_file).await?;
+
+        metadata.save().await?;
+
+        if let Some(content_hash) = &metadata.content_hash {
+            record_hash(content_hash, id).await?;
         }
+
+        Ok(metadata)
+    }
+
+    // Link to the best rendition for embedding in a Discord reply
+    pub async fn get_reply_url(metadata: &VideoMetadata) -> Result<String> {
+        let file_name = metadata
+            .rendition_for_height(CONFIG.reply_max_height)
+            .map(|rendition| rendition.file_name.clone())
+            .unwrap_or_else(|| format!("{}.mp4", metadata.id));
+
+        Self::get_file_url(&file_name).await
     }
 
-    pub fn get_file_url(file_name: &str) -> String {
-        format!("{}/{}", CONFIG.public_url, file_name)
+    pub async fn get_file_url(file_name: &str) -> Result<String> {
+        let url = STORAGE.url(file_name).await?;
+
+        // Remote backends sign their own URLs, local files are served by the web server
+        if CONFIG.storage_backend == "local" {
+            Ok(sign_url(&url, &format!("/{}", file_name)))
+        } else {
+            Ok(url)
+        }
     }
 }
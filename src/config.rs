@@ -1,24 +1,107 @@
 use lazy_static::lazy_static;
+use std::collections::HashMap;
 use std::env;
+use std::time::Duration;
+
+use crate::ratelimit::RateLimit;
+use crate::settings::ReplyMode;
 
 pub struct Config {
     pub discord_token: String,
+    pub log_format: String,
+    pub log_filter: String,
+    pub otlp_endpoint: Option<String>,
+    pub otlp_service_name: String,
     pub download_dir: String,
     pub converted_dir: String,
+    pub metadata_dir: String,
     pub ffmpeg_bin: String,
     pub ffmpeg_args: String,
+    pub ffmpeg_profiles: HashMap<String, String>,
+    pub ffprobe_bin: String,
+    pub ffmpeg_timeout: Duration,
+    pub ffmpeg_nice: Option<i32>,
+    pub ffmpeg_ionice_class: Option<i32>,
+    pub ffmpeg_ionice_level: i32,
+    pub ffmpeg_max_memory: Option<u64>,
+    pub ffmpeg_max_cpu_time: Option<u64>,
+    pub job_timeout: Duration,
+    pub shutdown_timeout: Duration,
+    pub metrics_token: Option<String>,
+    pub disk_usage_interval: Duration,
+    pub pending_jobs_file: String,
+    pub allowed_containers: Vec<String>,
+    pub max_video_duration: f64,
+    pub max_video_width: u32,
+    pub max_video_height: u32,
+    pub max_video_frame_rate: f64,
+    pub perceptual_dedup: bool,
+    pub perceptual_dedup_threshold: u32,
     pub host: String,
     pub port: u16,
     pub public_url: String,
+    pub storage_backend: String,
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    pub s3_public_url: Option<String>,
+    pub s3_url_expiry: u64,
+    pub url_signing_secret: Option<String>,
+    pub url_signing_expiry: u64,
+    pub signed_private_videos: bool,
+    pub hidden_guilds: Vec<u64>,
+    pub allowed_guilds: Vec<u64>,
+    pub denied_guilds: Vec<u64>,
+    pub allowed_channels: Vec<u64>,
+    pub denied_channels: Vec<u64>,
+    pub allow_dms: bool,
+    pub guild_settings_file: String,
+    pub audit_log_file: String,
+    pub discord_client_id: Option<String>,
+    pub discord_client_secret: Option<String>,
+    pub discord_api_url: String,
+    pub discord_authorize_url: String,
+    pub session_ttl: Duration,
+    pub admin_user_ids: Vec<u64>,
+    pub user_rate_limit: Option<RateLimit>,
+    pub channel_rate_limit: Option<RateLimit>,
+    pub guild_rate_limit: Option<RateLimit>,
+    pub web_rate_limit: Option<RateLimit>,
+    pub trust_forwarded_for: bool,
+    pub forwarded_hops: usize,
+    pub thumbnail_concurrency: usize,
+    pub thumbnail_queue_size: usize,
+    pub thumbnail_max_attempts: u32,
+    pub thumbnail_retry_backoff: Duration,
+    pub thumbnail_negative_ttl: Duration,
+    pub hls_enabled: bool,
+    pub hls_min_duration: f64,
+    pub hls_min_bitrate: u64,
+    pub hls_js_file: String,
+    pub renditions_enabled: bool,
+    pub reply_max_height: u32,
+    pub reply_mode: ReplyMode,
+    pub suppress_embeds: bool,
+    pub discord_upload_enabled: bool,
+    pub discord_upload_reencode: bool,
+    pub delete_buttons: bool,
 }
 
 impl Config {
     pub fn new() -> Self {
         let discord_token = env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN not set!");
 
+        let log_format = env::var("LOG_FORMAT").unwrap_or("pretty".to_string());
+        let log_filter = env::var("RUST_LOG").unwrap_or("info".to_string());
+        let otlp_endpoint = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok();
+        let otlp_service_name = env::var("OTEL_SERVICE_NAME").unwrap_or("cerebro".to_string());
+
         let data_path = env::var("DATA_PATH").unwrap_or(".".to_string());
         let download_dir = format!("{}/downloads", data_path);
         let converted_dir = format!("{}/converted", data_path);
+        let metadata_dir = format!("{}/metadata", data_path);
 
         let ffmpeg_bin = env::var("FFMPEG_BIN").unwrap_or("ffmpeg".to_string());
         let ffmpeg_args = env::var("FFMPEG_ARGS").unwrap_or(
@@ -38,6 +121,105 @@ impl Config {
                 + "$OUTPUT",
         );
 
+        // Alternative argument templates guilds can choose, as "name=args;name=args"
+        let ffmpeg_profiles = env::var("FFMPEG_PROFILES")
+            .unwrap_or_default()
+            .split(';')
+            .map(str::trim)
+            .filter(|profile| !profile.is_empty())
+            .map(|profile| {
+                let (name, args) = profile
+                    .split_once('=')
+                    .expect("FFMPEG_PROFILES entries must be in the form name=args");
+                (name.trim().to_string(), args.trim().to_string())
+            })
+            .collect();
+
+        let ffprobe_bin = env::var("FFPROBE_BIN").unwrap_or("ffprobe".to_string());
+
+        let ffmpeg_timeout = Duration::from_secs(
+            env::var("FFMPEG_TIMEOUT")
+                .unwrap_or("3600".to_string())
+                .parse()
+                .expect("FFMPEG_TIMEOUT must be a number of seconds"),
+        );
+        let ffmpeg_nice = env::var("FFMPEG_NICE")
+            .ok()
+            .map(|nice| nice.parse().expect("FFMPEG_NICE must be a valid i32"));
+        let ffmpeg_ionice_class = env::var("FFMPEG_IONICE_CLASS").ok().map(|class| {
+            class
+                .parse()
+                .expect("FFMPEG_IONICE_CLASS must be 1 (realtime), 2 (best-effort) or 3 (idle)")
+        });
+        let ffmpeg_ionice_level = env::var("FFMPEG_IONICE_LEVEL")
+            .unwrap_or("4".to_string())
+            .parse()
+            .expect("FFMPEG_IONICE_LEVEL must be between 0 and 7");
+        let ffmpeg_max_memory = env::var("FFMPEG_MAX_MEMORY").ok().map(|memory| {
+            memory
+                .parse()
+                .expect("FFMPEG_MAX_MEMORY must be a size in MiB")
+        });
+        let ffmpeg_max_cpu_time = env::var("FFMPEG_MAX_CPU_TIME").ok().map(|seconds| {
+            seconds
+                .parse()
+                .expect("FFMPEG_MAX_CPU_TIME must be a number of seconds")
+        });
+        let job_timeout = Duration::from_secs(
+            env::var("JOB_TIMEOUT")
+                .unwrap_or("1800".to_string())
+                .parse()
+                .expect("JOB_TIMEOUT must be a number of seconds"),
+        );
+        let shutdown_timeout = Duration::from_secs(
+            env::var("SHUTDOWN_TIMEOUT")
+                .unwrap_or("25".to_string())
+                .parse()
+                .expect("SHUTDOWN_TIMEOUT must be a number of seconds"),
+        );
+        let pending_jobs_file = format!("{}/pending_jobs.json", data_path);
+        let metrics_token = env::var("METRICS_TOKEN").ok();
+        let disk_usage_interval = Duration::from_secs(
+            env::var("DISK_USAGE_INTERVAL")
+                .unwrap_or("300".to_string())
+                .parse()
+                .ok()
+                .filter(|seconds| *seconds > 0)
+                .expect("DISK_USAGE_INTERVAL must be a positive number of seconds"),
+        );
+
+        let allowed_containers = env::var("ALLOWED_CONTAINERS")
+            .unwrap_or("mov,mp4,m4a,3gp,3g2,mj2,matroska,webm".to_string())
+            .split(',')
+            .map(|format| format.trim().to_string())
+            .filter(|format| !format.is_empty())
+            .collect();
+        let max_video_duration = env::var("MAX_VIDEO_DURATION")
+            .unwrap_or("3600".to_string())
+            .parse()
+            .expect("MAX_VIDEO_DURATION must be a number of seconds");
+        let max_video_width = env::var("MAX_VIDEO_WIDTH")
+            .unwrap_or("3840".to_string())
+            .parse()
+            .expect("MAX_VIDEO_WIDTH must be a valid u32");
+        let max_video_height = env::var("MAX_VIDEO_HEIGHT")
+            .unwrap_or("2160".to_string())
+            .parse()
+            .expect("MAX_VIDEO_HEIGHT must be a valid u32");
+        let max_video_frame_rate = env::var("MAX_VIDEO_FRAME_RATE")
+            .unwrap_or("120".to_string())
+            .parse()
+            .expect("MAX_VIDEO_FRAME_RATE must be a number");
+
+        let perceptual_dedup = env::var("PERCEPTUAL_DEDUP")
+            .unwrap_or("false".to_string())
+            .parse()
+            .expect("PERCEPTUAL_DEDUP must be true or false");
+        let perceptual_dedup_threshold = env::var("PERCEPTUAL_DEDUP_THRESHOLD")
+            .unwrap_or("10".to_string())
+            .parse()
+            .expect("PERCEPTUAL_DEDUP_THRESHOLD must be a valid u32");
+
         let host = env::var("WEBSERVER_HOST").unwrap_or("127.0.0.1".to_string());
         let port = env::var("WEBSERVER_PORT")
             .unwrap_or("8080".to_string())
@@ -46,15 +228,260 @@ impl Config {
 
         let public_url = env::var("PUBLIC_URL").unwrap_or(format!("http://{host}:{port}"));
 
+        let storage_backend = env::var("STORAGE_BACKEND").unwrap_or("local".to_string());
+        let s3_endpoint = env::var("S3_ENDPOINT").ok();
+        let s3_bucket = env::var("S3_BUCKET").ok();
+        let s3_region = env::var("S3_REGION").unwrap_or("us-east-1".to_string());
+        let s3_access_key = env::var("S3_ACCESS_KEY").ok();
+        let s3_secret_key = env::var("S3_SECRET_KEY").ok();
+        let s3_public_url = env::var("S3_PUBLIC_URL").ok();
+        let s3_url_expiry = env::var("S3_URL_EXPIRY")
+            .unwrap_or("604800".to_string())
+            .parse()
+            .expect("S3_URL_EXPIRY must be a number of seconds");
+
+        let url_signing_secret = env::var("URL_SIGNING_SECRET").ok();
+        let url_signing_expiry = env::var("URL_SIGNING_EXPIRY")
+            .unwrap_or("604800".to_string())
+            .parse()
+            .expect("URL_SIGNING_EXPIRY must be a number of seconds");
+        let signed_private_videos = env::var("SIGNED_PRIVATE_VIDEOS")
+            .unwrap_or("false".to_string())
+            .parse()
+            .expect("SIGNED_PRIVATE_VIDEOS must be true or false");
+        if signed_private_videos && url_signing_secret.is_none() {
+            panic!("URL_SIGNING_SECRET must be set when SIGNED_PRIVATE_VIDEOS is enabled");
+        }
+
+        let hidden_guilds = env::var("HIDDEN_GUILDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|guild_id| !guild_id.is_empty())
+            .map(|guild_id| {
+                guild_id
+                    .parse()
+                    .expect("HIDDEN_GUILDS must be a comma-separated list of guild IDs")
+            })
+            .collect();
+
+        let id_list = |name: &str| -> Vec<u64> {
+            env::var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(|id| {
+                    id.parse().unwrap_or_else(|_| {
+                        panic!("{} must be a comma-separated list of IDs", name)
+                    })
+                })
+                .collect()
+        };
+        let allowed_guilds = id_list("ALLOWED_GUILDS");
+        let denied_guilds = id_list("DENIED_GUILDS");
+        let allowed_channels = id_list("ALLOWED_CHANNELS");
+        let denied_channels = id_list("DENIED_CHANNELS");
+        let allow_dms = env::var("ALLOW_DMS")
+            .unwrap_or("true".to_string())
+            .parse()
+            .expect("ALLOW_DMS must be true or false");
+        let guild_settings_file = format!("{}/guilds.json", data_path);
+        let audit_log_file = format!("{}/audit.log", data_path);
+
+        let discord_client_id = env::var("DISCORD_CLIENT_ID").ok();
+        let discord_client_secret = env::var("DISCORD_CLIENT_SECRET").ok();
+        let discord_api_url =
+            env::var("DISCORD_API_URL").unwrap_or("https://discord.com/api".to_string());
+        let discord_authorize_url = env::var("DISCORD_AUTHORIZE_URL")
+            .unwrap_or("https://discord.com/oauth2/authorize".to_string());
+        let session_ttl = Duration::from_secs(
+            env::var("SESSION_TTL")
+                .unwrap_or("604800".to_string())
+                .parse()
+                .expect("SESSION_TTL must be a number of seconds"),
+        );
+        let admin_user_ids = env::var("ADMIN_USER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|user_id| !user_id.is_empty())
+            .map(|user_id| {
+                user_id
+                    .parse()
+                    .expect("ADMIN_USER_IDS must be a comma-separated list of user IDs")
+            })
+            .collect();
+
+        let rate_limit = |name: &str, default: &str| {
+            RateLimit::parse(&env::var(name).unwrap_or(default.to_string()))
+                .unwrap_or_else(|| panic!("{} must be in the form capacity/seconds", name))
+        };
+        let user_rate_limit = rate_limit("USER_RATE_LIMIT", "0/0");
+        let channel_rate_limit = rate_limit("CHANNEL_RATE_LIMIT", "0/0");
+        let guild_rate_limit = rate_limit("GUILD_RATE_LIMIT", "0/0");
+        let web_rate_limit = rate_limit("WEB_RATE_LIMIT", "0/0");
+        let trust_forwarded_for = env::var("TRUST_FORWARDED_FOR")
+            .unwrap_or("false".to_string())
+            .parse()
+            .expect("TRUST_FORWARDED_FOR must be true or false");
+        let forwarded_hops = env::var("FORWARDED_HOPS")
+            .unwrap_or("1".to_string())
+            .parse()
+            .ok()
+            .filter(|hops| *hops > 0)
+            .expect("FORWARDED_HOPS must be a positive number");
+
+        let thumbnail_concurrency = env::var("THUMBNAIL_CONCURRENCY")
+            .unwrap_or("2".to_string())
+            .parse()
+            .expect("THUMBNAIL_CONCURRENCY must be a valid usize");
+        let thumbnail_queue_size = env::var("THUMBNAIL_QUEUE_SIZE")
+            .unwrap_or("1000".to_string())
+            .parse()
+            .ok()
+            .filter(|size| *size > 0)
+            .expect("THUMBNAIL_QUEUE_SIZE must be a positive number");
+        let thumbnail_max_attempts = env::var("THUMBNAIL_MAX_ATTEMPTS")
+            .unwrap_or("3".to_string())
+            .parse()
+            .expect("THUMBNAIL_MAX_ATTEMPTS must be a valid u32");
+        let thumbnail_retry_backoff = Duration::from_secs(
+            env::var("THUMBNAIL_RETRY_BACKOFF")
+                .unwrap_or("10".to_string())
+                .parse()
+                .expect("THUMBNAIL_RETRY_BACKOFF must be a number of seconds"),
+        );
+        let thumbnail_negative_ttl = Duration::from_secs(
+            env::var("THUMBNAIL_NEGATIVE_TTL")
+                .unwrap_or("3600".to_string())
+                .parse()
+                .expect("THUMBNAIL_NEGATIVE_TTL must be a number of seconds"),
+        );
+
+        let hls_enabled = env::var("HLS_ENABLED")
+            .unwrap_or("false".to_string())
+            .parse()
+            .expect("HLS_ENABLED must be true or false");
+        let hls_min_duration = env::var("HLS_MIN_DURATION")
+            .unwrap_or("300".to_string())
+            .parse()
+            .expect("HLS_MIN_DURATION must be a number of seconds");
+        let hls_min_bitrate = env::var("HLS_MIN_BITRATE")
+            .unwrap_or("4000".to_string())
+            .parse()
+            .expect("HLS_MIN_BITRATE must be a bitrate in kbit/s");
+        let hls_js_file = env::var("HLS_JS_FILE").unwrap_or(format!("{}/hls.min.js", data_path));
+
+        let renditions_enabled = env::var("RENDITIONS_ENABLED")
+            .unwrap_or("false".to_string())
+            .parse()
+            .expect("RENDITIONS_ENABLED must be true or false");
+        let reply_max_height = env::var("REPLY_MAX_HEIGHT")
+            .unwrap_or("720".to_string())
+            .parse()
+            .expect("REPLY_MAX_HEIGHT must be a valid u32");
+        let reply_mode = ReplyMode::parse(&env::var("REPLY_MODE").unwrap_or("channel".to_string()))
+            .expect("REPLY_MODE must be channel, reply or thread");
+        let suppress_embeds = env::var("SUPPRESS_EMBEDS")
+            .unwrap_or("false".to_string())
+            .parse()
+            .expect("SUPPRESS_EMBEDS must be true or false");
+
+        let discord_upload_enabled = env::var("DISCORD_UPLOAD_ENABLED")
+            .unwrap_or("false".to_string())
+            .parse()
+            .expect("DISCORD_UPLOAD_ENABLED must be true or false");
+        let discord_upload_reencode = env::var("DISCORD_UPLOAD_REENCODE")
+            .unwrap_or("true".to_string())
+            .parse()
+            .expect("DISCORD_UPLOAD_REENCODE must be true or false");
+        let delete_buttons = env::var("DELETE_BUTTONS")
+            .unwrap_or("true".to_string())
+            .parse()
+            .expect("DELETE_BUTTONS must be true or false");
+
         Self {
             discord_token,
+            log_format,
+            log_filter,
+            otlp_endpoint,
+            otlp_service_name,
             public_url,
             download_dir,
             converted_dir,
+            metadata_dir,
             ffmpeg_bin,
             ffmpeg_args,
+            ffmpeg_profiles,
+            ffprobe_bin,
+            ffmpeg_timeout,
+            ffmpeg_nice,
+            ffmpeg_ionice_class,
+            ffmpeg_ionice_level,
+            ffmpeg_max_memory,
+            ffmpeg_max_cpu_time,
+            job_timeout,
+            shutdown_timeout,
+            metrics_token,
+            disk_usage_interval,
+            pending_jobs_file,
+            allowed_containers,
+            max_video_duration,
+            max_video_width,
+            max_video_height,
+            max_video_frame_rate,
+            perceptual_dedup,
+            perceptual_dedup_threshold,
             host,
             port,
+            storage_backend,
+            s3_endpoint,
+            s3_bucket,
+            s3_region,
+            s3_access_key,
+            s3_secret_key,
+            s3_public_url,
+            s3_url_expiry,
+            url_signing_secret,
+            url_signing_expiry,
+            signed_private_videos,
+            hidden_guilds,
+            allowed_guilds,
+            denied_guilds,
+            allowed_channels,
+            denied_channels,
+            allow_dms,
+            guild_settings_file,
+            audit_log_file,
+            discord_client_id,
+            discord_client_secret,
+            discord_api_url,
+            discord_authorize_url,
+            session_ttl,
+            admin_user_ids,
+            user_rate_limit,
+            channel_rate_limit,
+            guild_rate_limit,
+            web_rate_limit,
+            trust_forwarded_for,
+            forwarded_hops,
+            thumbnail_concurrency,
+            thumbnail_queue_size,
+            thumbnail_max_attempts,
+            thumbnail_retry_backoff,
+            thumbnail_negative_ttl,
+            hls_enabled,
+            hls_min_duration,
+            hls_min_bitrate,
+            hls_js_file,
+            renditions_enabled,
+            reply_max_height,
+            reply_mode,
+            suppress_embeds,
+            discord_upload_enabled,
+            discord_upload_reencode,
+            delete_buttons,
         }
     }
 }
@@ -0,0 +1,184 @@
+use async_process::Command;
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::Duration;
+use tokio::fs;
+use tracing::debug;
+
+use crate::config::CONFIG;
+use crate::jobs::JOBS;
+
+// ioprio_set(2) constants, not exposed by libc
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+// Resource limits applied to ffmpeg and ffprobe children
+#[derive(Clone, Copy)]
+struct Limits {
+    nice: Option<i32>,
+    ionice: Option<(i32, i32)>,
+    max_memory: Option<u64>,
+    max_cpu_time: Option<u64>,
+}
+
+impl Limits {
+    fn from_config() -> Self {
+        Self {
+            nice: CONFIG.ffmpeg_nice,
+            ionice: CONFIG
+                .ffmpeg_ionice_class
+                .map(|class| (class, CONFIG.ffmpeg_ionice_level)),
+            max_memory: CONFIG.ffmpeg_max_memory.map(|mib| mib * 1024 * 1024),
+            max_cpu_time: CONFIG.ffmpeg_max_cpu_time,
+        }
+    }
+
+    // Runs in the forked child before exec, so only async-signal-safe calls are allowed
+    fn apply(self) -> io::Result<()> {
+        unsafe {
+            if let Some(nice) = self.nice
+                && libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some((class, level)) = self.ionice
+                && libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    0,
+                    (class << IOPRIO_CLASS_SHIFT) | level,
+                ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some(max_memory) = self.max_memory
+                && libc::setrlimit(libc::RLIMIT_AS, &rlimit(max_memory)) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some(max_cpu_time) = self.max_cpu_time
+                && libc::setrlimit(libc::RLIMIT_CPU, &rlimit(max_cpu_time)) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn rlimit(value: u64) -> libc::rlimit {
+    libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    }
+}
+
+// Build a command that runs with the configured resource limits and is killed when dropped
+pub fn command(program: &str) -> Command {
+    let limits = Limits::from_config();
+    let mut command = std::process::Command::new(program);
+
+    // SAFETY: the closure only performs async-signal-safe syscalls
+    unsafe {
+        command.pre_exec(move || limits.apply());
+    }
+
+    let mut command = Command::from(command);
+    command.kill_on_drop(true);
+    command
+}
+
+// Collect the output of a command, killing it if it runs past the configured timeout
+pub async fn output(mut command: Command) -> io::Result<Output> {
+    match tokio::time::timeout(CONFIG.ffmpeg_timeout, command.output()).await {
+        Ok(output) => output,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "{} timed out after {:?}",
+                command.get_program().to_string_lossy(),
+                CONFIG.ffmpeg_timeout
+            ),
+        )),
+    }
+}
+
+// Run ffmpeg and account its CPU time to a job
+pub async fn run_ffmpeg<S: AsRef<OsStr>>(args: &[S], job: Option<&str>) -> io::Result<Output> {
+    let mut command = command(&CONFIG.ffmpeg_bin);
+    command.arg("-benchmark").args(args);
+
+    let output = output(command).await?;
+
+    if let Some(cpu_time) = parse_cpu_time(&output.stderr) {
+        debug!("ffmpeg used {:?} of CPU time", cpu_time);
+        if let Some(job) = job {
+            JOBS.add_cpu_time(job, cpu_time).await;
+        }
+    }
+
+    Ok(output)
+}
+
+// Run ffmpeg, turning a failed exit into an error carrying its output
+pub async fn run_checked<S: AsRef<OsStr>>(
+    args: &[S],
+    job: Option<&str>,
+    action: &str,
+) -> io::Result<()> {
+    let output = run_ffmpeg(args, job).await?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("Failed to {}: {}", action, error)));
+    }
+
+    Ok(())
+}
+
+// Write a generated file through a temporary path next to it, so an existing file is
+// always complete. The extension is kept for ffmpeg to pick the output format
+pub async fn write_atomically<F, Fut>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let temp_path = path.with_extension(format!("tmp.{}", extension));
+
+    match write(temp_path.clone()).await {
+        Ok(()) => fs::rename(&temp_path, path).await,
+        Err(e) => {
+            fs::remove_file(&temp_path).await.ok();
+            Err(e)
+        }
+    }
+}
+
+// Parse the user and system time from the line printed by -benchmark
+fn parse_cpu_time(stderr: &[u8]) -> Option<Duration> {
+    let stderr = String::from_utf8_lossy(stderr);
+    let line = stderr
+        .lines()
+        .rev()
+        .find(|line| line.starts_with("bench: utime="))?;
+
+    let mut total = 0.0;
+    for field in line.trim_start_matches("bench: ").split_whitespace() {
+        if let Some(value) = field
+            .strip_prefix("utime=")
+            .or_else(|| field.strip_prefix("stime="))
+        {
+            total += value.trim_end_matches('s').parse::<f64>().ok()?;
+        }
+    }
+
+    Some(Duration::from_secs_f64(total))
+}
@@ -0,0 +1,178 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::sync::Mutex;
+
+use crate::config::CONFIG;
+use crate::ffmpeg;
+use crate::jobs::JOBS;
+
+// How long an ffmpeg availability check is reused, spawning it on every probe is wasteful
+const TOOLS_CHECK_TTL: Duration = Duration::from_secs(60);
+
+// Extra time a job may run past its timeout before it's considered stuck
+const JOB_GRACE: Duration = Duration::from_secs(60);
+
+// Result of a single readiness check
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    pub fn new(name: &'static str, result: Result<(), String>) -> Self {
+        Self {
+            name,
+            ok: result.is_ok(),
+            detail: result.err(),
+        }
+    }
+}
+
+// Liveness of the application's components, shared between the bot, web server and supervisor
+pub struct Health {
+    gateway_connected: AtomicBool,
+    bot_running: AtomicBool,
+    web_running: AtomicBool,
+    shutting_down: AtomicBool,
+    tools: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+impl Health {
+    fn new() -> Self {
+        Self {
+            gateway_connected: AtomicBool::new(false),
+            bot_running: AtomicBool::new(false),
+            web_running: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            tools: Mutex::new(None),
+        }
+    }
+
+    pub fn set_gateway_connected(&self, connected: bool) {
+        self.gateway_connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn set_bot_running(&self, running: bool) {
+        self.bot_running.store(running, Ordering::Relaxed);
+        if !running {
+            self.set_gateway_connected(false);
+        }
+    }
+
+    pub fn set_web_running(&self, running: bool) {
+        self.web_running.store(running, Ordering::Relaxed);
+    }
+
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    // Both components are running, restarts in progress count as not alive
+    pub fn liveness(&self) -> Vec<Check> {
+        let running = |flag: &AtomicBool, name: &str| {
+            if flag.load(Ordering::Relaxed) {
+                Ok(())
+            } else {
+                Err(format!("{} is not running", name))
+            }
+        };
+
+        vec![
+            Check::new("bot", running(&self.bot_running, "Discord bot")),
+            Check::new("web", running(&self.web_running, "Web server")),
+        ]
+    }
+
+    // Whether the application can do useful work right now
+    pub async fn readiness(&self) -> Vec<Check> {
+        let gateway = if self.gateway_connected.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err("Not connected to the Discord gateway".to_string())
+        };
+
+        vec![
+            Check::new("gateway", gateway),
+            Check::new("ffmpeg", self.check_tools().await),
+            Check::new("data_dirs", check_data_dirs().await),
+            Check::new("jobs", check_jobs().await),
+        ]
+    }
+
+    async fn check_tools(&self) -> Result<(), String> {
+        let mut cached = self.tools.lock().await;
+        if let Some((checked_at, result)) = cached.as_ref()
+            && checked_at.elapsed() < TOOLS_CHECK_TTL
+        {
+            return result.clone();
+        }
+
+        let mut result = Ok(());
+        for program in [&CONFIG.ffmpeg_bin, &CONFIG.ffprobe_bin] {
+            let mut command = ffmpeg::command(program);
+            command.arg("-version");
+            match ffmpeg::output(command).await {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => {
+                    result = Err(format!(
+                        "{} -version exited with {}",
+                        program, output.status
+                    ));
+                    break;
+                }
+                Err(e) => {
+                    result = Err(format!("{} is not available: {}", program, e));
+                    break;
+                }
+            }
+        }
+
+        *cached = Some((Instant::now(), result.clone()));
+        result
+    }
+}
+
+// Every data directory accepts writes
+async fn check_data_dirs() -> Result<(), String> {
+    for dir in [
+        &CONFIG.download_dir,
+        &CONFIG.converted_dir,
+        &CONFIG.metadata_dir,
+    ] {
+        let probe = Path::new(dir).join(".healthcheck");
+        fs::write(&probe, b"ok")
+            .await
+            .map_err(|e| format!("{} is not writable: {}", dir, e))?;
+        fs::remove_file(&probe).await.ok();
+    }
+
+    Ok(())
+}
+
+// No job has outlived its timeout, which would mean the queue is wedged
+async fn check_jobs() -> Result<(), String> {
+    let limit = CONFIG.job_timeout + JOB_GRACE;
+    for job in JOBS.list().await {
+        let elapsed = job.started_at.elapsed().unwrap_or_default();
+        if elapsed > limit {
+            return Err(format!("Job {} has been running for {:?}", job.id, elapsed));
+        }
+    }
+
+    Ok(())
+}
+
+lazy_static! {
+    pub static ref HEALTH: Health = Health::new();
+}
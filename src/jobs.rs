@@ -0,0 +1,176 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{watch, Mutex};
+
+// Failures kept around for inspection, oldest are dropped first
+const MAX_FAILURES: usize = 50;
+
+// Where a conversion was requested from
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Origin {
+    pub private: bool,
+    pub guild_id: Option<u64>,
+    #[serde(default)]
+    pub channel_id: Option<u64>,
+    // Message containing the link, replied to if the job outlives its handler
+    #[serde(default)]
+    pub message_id: Option<u64>,
+    pub submitter_id: Option<u64>,
+}
+
+// A conversion requested from Discord
+#[derive(Clone)]
+pub struct Job {
+    pub id: String,
+    pub source_url: String,
+    pub origin: Origin,
+    pub started_at: SystemTime,
+    // CPU time used by all ffmpeg processes of the job
+    pub cpu_time: Duration,
+}
+
+// How a job ended, shared with the requests for the same video that joined it
+#[derive(Clone)]
+pub enum Outcome {
+    // ID of the stored video, which differs from the job's for duplicates
+    Converted(String),
+    // Reason shown to the requester as-is
+    Rejected(String),
+    Failed(String),
+}
+
+// Receives the outcome of a running job
+pub type OutcomeReceiver = watch::Receiver<Option<Outcome>>;
+
+struct RunningJob {
+    job: Job,
+    outcome: watch::Sender<Option<Outcome>>,
+}
+
+// A job that ended in an error, with enough information to retry it
+#[derive(Clone)]
+pub struct Failure {
+    pub id: String,
+    pub source_url: String,
+    pub origin: Origin,
+    // Full error chain, including ffmpeg's stderr for failed encodes
+    pub error: String,
+    pub failed_at: SystemTime,
+}
+
+// Jobs that are currently being processed, keyed by video ID, and recent failures
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, RunningJob>>,
+    failures: Mutex<VecDeque<Failure>>,
+}
+
+impl JobRegistry {
+    fn new() -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            failures: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Register a job, or hand back the outcome to wait for if the video is already being
+    // converted
+    pub async fn start(
+        &self,
+        id: &str,
+        source_url: &str,
+        origin: Origin,
+    ) -> Result<(), OutcomeReceiver> {
+        let mut jobs = self.jobs.lock().await;
+        if let Some(running) = jobs.get(id) {
+            return Err(running.outcome.subscribe());
+        }
+
+        jobs.insert(
+            id.to_string(),
+            RunningJob {
+                job: Job {
+                    id: id.to_string(),
+                    source_url: source_url.to_string(),
+                    origin,
+                    started_at: SystemTime::now(),
+                    cpu_time: Duration::ZERO,
+                },
+                outcome: watch::Sender::new(None),
+            },
+        );
+        Ok(())
+    }
+
+    pub async fn is_running(&self, id: &str) -> bool {
+        self.jobs.lock().await.contains_key(id)
+    }
+
+    // Add CPU time to a job, ignored if the job has already finished
+    pub async fn add_cpu_time(&self, id: &str, cpu_time: Duration) {
+        if let Some(running) = self.jobs.lock().await.get_mut(id) {
+            running.job.cpu_time += cpu_time;
+        }
+    }
+
+    // Remove a job and pass its outcome to the requests waiting for it
+    pub async fn finish(&self, id: &str, outcome: Outcome) -> Option<Job> {
+        let running = self.jobs.lock().await.remove(id)?;
+        running.outcome.send_replace(Some(outcome));
+        Some(running.job)
+    }
+
+    // Running jobs, oldest first
+    pub async fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<_> = self
+            .jobs
+            .lock()
+            .await
+            .values()
+            .map(|running| running.job.clone())
+            .collect();
+        jobs.sort_by_key(|job| job.started_at);
+        jobs
+    }
+
+    pub async fn record_failure(
+        &self,
+        id: &str,
+        source_url: &str,
+        origin: Origin,
+        error: &anyhow::Error,
+    ) {
+        let mut failures = self.failures.lock().await;
+
+        // Only the latest failure of a video is interesting
+        failures.retain(|failure| failure.id != id);
+        failures.push_back(Failure {
+            id: id.to_string(),
+            source_url: source_url.to_string(),
+            origin,
+            error: format!("{:#}", error),
+            failed_at: SystemTime::now(),
+        });
+
+        while failures.len() > MAX_FAILURES {
+            failures.pop_front();
+        }
+    }
+
+    // Recent failures, newest first
+    pub async fn failures(&self) -> Vec<Failure> {
+        self.failures.lock().await.iter().rev().cloned().collect()
+    }
+
+    // Remove a failure so it can be retried
+    pub async fn take_failure(&self, id: &str) -> Option<Failure> {
+        let mut failures = self.failures.lock().await;
+        let index = failures.iter().position(|failure| failure.id == id)?;
+        failures.remove(index)
+    }
+}
+
+lazy_static! {
+    pub static ref JOBS: JobRegistry = JobRegistry::new();
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tracing::{error, info};
+
+use crate::config::CONFIG;
+use crate::metadata::VideoMetadata;
+use crate::metrics::STORAGE_BYTES;
+use crate::storage::STORAGE;
+
+// Remove a video with every file derived from it
+pub async fn delete_video(metadata: &VideoMetadata) -> Result<()> {
+    let id = &metadata.id;
+
+    // Published files, including renditions and HLS segments under {id}/
+    let mut keys: HashSet<String> = metadata
+        .renditions
+        .iter()
+        .map(|rendition| rendition.file_name.clone())
+        .collect();
+    keys.insert(format!("{}.mp4", id));
+    match STORAGE.list(&format!("{}/", id)).await {
+        Ok(listed) => keys.extend(listed),
+        Err(e) => error!("Failed to list stored files of {}: {:?}", id, e),
+    }
+    for key in &keys {
+        if let Err(e) = STORAGE.delete(key).await {
+            info!("Could not remove {} from storage: {:?}", key, e);
+        }
+    }
+
+    // Local copies and generated thumbnails
+    let converted_dir = Path::new(&CONFIG.converted_dir);
+    fs::remove_file(converted_dir.join(format!("{}.mp4", id)))
+        .await
+        .ok();
+    fs::remove_dir_all(converted_dir.join(id)).await.ok();
+
+    remove_thumbnails(id).await?;
+
+    metadata.delete().await?;
+    info!("Deleted video {}", id);
+
+    Ok(())
+}
+
+// Generated thumbnails and previews as suffix and extension, see the web thumbnails and
+// previews modules
+const THUMBNAIL_FILES: [(&str, &str); 11] = [
+    ("", "avif"),
+    ("", "webp"),
+    ("", "jpg"),
+    ("-poster", "avif"),
+    ("-poster", "webp"),
+    ("-poster", "jpg"),
+    ("-preview", "mp4"),
+    ("-sprite", "jpg"),
+    ("-seek", "jpg"),
+    ("-seek", "vtt"),
+    (".frame", "png"),
+];
+
+// Remove the generated thumbnails and previews of a video, matched by exact name so
+// videos whose ID starts with this one keep theirs
+pub async fn remove_thumbnails(id: &str) -> Result<()> {
+    let thumbs_dir = Path::new(&CONFIG.converted_dir).join("thumbs");
+    for (suffix, extension) in THUMBNAIL_FILES {
+        // Temporary files of an interrupted write go too
+        for name in [
+            format!("{}{}.{}", id, suffix, extension),
+            format!("{}{}.tmp.{}", id, suffix, extension),
+        ] {
+            match fs::remove_file(thumbs_dir.join(name)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Measure the data directories periodically, walking them is too slow for every scrape
+pub async fn measure_disk_usage() {
+    let mut interval = tokio::time::interval(CONFIG.disk_usage_interval);
+    loop {
+        interval.tick().await;
+        for (directory, path) in [
+            ("downloads", &CONFIG.download_dir),
+            ("converted", &CONFIG.converted_dir),
+            ("metadata", &CONFIG.metadata_dir),
+        ] {
+            let size = disk_usage(Path::new(path)).await;
+            STORAGE_BYTES
+                .with_label_values(&[directory])
+                .set(size as i64);
+        }
+    }
+}
+
+// Total size of the files below a directory
+pub async fn disk_usage(dir: &Path) -> u64 {
+    let mut total = 0;
+    let mut pending: Vec<PathBuf> = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(mut entries) = fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            match entry.metadata().await {
+                Ok(metadata) if metadata.is_dir() => pending.push(entry.path()),
+                Ok(metadata) => total += metadata.len(),
+                Err(_) => {}
+            }
+        }
+    }
+
+    total
+}
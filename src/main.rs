@@ -1,19 +1,87 @@
+mod audit;
 mod bot;
 mod config;
+mod ffmpeg;
+mod health;
+mod jobs;
+mod library;
+mod metadata;
+mod metrics;
+mod ratelimit;
+mod settings;
+mod shutdown;
+mod signing;
+mod storage;
+mod telemetry;
 mod web;
 
+use std::future::Future;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Notify;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::bot::client::start_bot;
 use crate::config::CONFIG;
+use crate::health::{Health, HEALTH};
+use crate::settings::SETTINGS;
 use crate::web::server::run_file_server;
 
+// Restarts in a row before the supervisor gives up and exits
+const MAX_RESTARTS: u32 = 5;
+
+// A component that ran at least this long is considered to have recovered
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+// Keep a component running, restarting it with backoff when it stops outside of a shutdown
+async fn supervise<F, Fut>(name: &'static str, set_running: fn(&Health, bool), start: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let mut failures = 0;
+
+    loop {
+        let started = Instant::now();
+        set_running(&HEALTH, true);
+        let result = start().await;
+        set_running(&HEALTH, false);
+
+        if HEALTH.is_shutting_down() {
+            info!("{} stopped", name);
+            return;
+        }
+
+        match result {
+            Ok(()) => error!("{} stopped unexpectedly", name),
+            Err(e) => error!("{} failed: {}", name, e),
+        }
+
+        if started.elapsed() >= STABLE_AFTER {
+            failures = 0;
+        }
+        failures += 1;
+
+        if failures > MAX_RESTARTS {
+            error!("{} failed {} times in a row, exiting", name, failures);
+            std::process::exit(1);
+        }
+
+        let backoff = Duration::from_secs(2u64.pow(failures - 1));
+        warn!("Restarting {} in {:?}", name, backoff);
+        tokio::time::sleep(backoff).await;
+
+        // The shutdown notification is missed while nothing is listening for it
+        if HEALTH.is_shutting_down() {
+            return;
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // Initialize logging
-    tracing_subscriber::fmt::init();
+    let tracer_provider = telemetry::init();
     info!("Logging initialized, starting the application");
 
     // Create required directories
@@ -23,28 +91,58 @@ async fn main() {
     tokio::fs::create_dir_all(&CONFIG.converted_dir)
         .await
         .expect("Failed to create converted directory");
+    tokio::fs::create_dir_all(&CONFIG.metadata_dir)
+        .await
+        .expect("Failed to create metadata directory");
+
+    // Load guild settings now so a broken file stops startup rather than the first message
+    lazy_static::initialize(&SETTINGS);
 
     // Create shutdown signal
     let shutdown = Arc::new(Notify::new());
 
+    // Stop both components once running jobs have drained after SIGINT or SIGTERM
+    let signal_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown::wait_for_signal().await;
+        shutdown::drain().await;
+        signal_shutdown.notify_waiters();
+    });
+
+    // Pick up where the last shutdown left off
+    shutdown::resume_pending().await;
+
     // Start web server
     let web_shutdown = shutdown.clone();
-    let web_server_handle = tokio::spawn(async move {
-        if let Err(e) = run_file_server(web_shutdown).await {
-            error!("File server error: {:?}", e);
-        }
-    });
+    let web_server_handle = tokio::spawn(supervise(
+        "Web server",
+        Health::set_web_running,
+        move || {
+            let shutdown = web_shutdown.clone();
+            async move { run_file_server(shutdown).await.map_err(|e| e.to_string()) }
+        },
+    ));
 
     // Start Discord bot
     let bot_shutdown = shutdown.clone();
-    let bot_handle = tokio::spawn(async move {
-        if let Err(e) = start_bot(bot_shutdown).await {
-            error!("Bot error: {:?}", e);
-        }
-    });
+    let bot_handle = tokio::spawn(supervise(
+        "Discord bot",
+        Health::set_bot_running,
+        move || {
+            let shutdown = bot_shutdown.clone();
+            async move { start_bot(shutdown).await.map_err(|e| e.to_string()) }
+        },
+    ));
 
     // Wait for both tasks to complete
     let _ = tokio::join!(web_server_handle, bot_handle);
 
+    // Export the spans that are still buffered
+    if let Some(tracer_provider) = tracer_provider
+        && let Err(e) = tracer_provider.shutdown()
+    {
+        error!("Failed to flush traces: {:?}", e);
+    }
+
     info!("Shutdown complete");
 }
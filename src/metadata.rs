@@ -0,0 +1,319 @@
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::config::CONFIG;
+use crate::jobs::Origin;
+
+lazy_static! {
+    // Held while a video's metadata is written, entries are dropped once nobody holds them
+    static ref LOCKS: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+// Gives every write its own temporary file
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+async fn lock(id: &str) -> OwnedMutexGuard<()> {
+    let lock = {
+        let mut locks = LOCKS.lock().await;
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+        locks.entry(id.to_string()).or_default().clone()
+    };
+    lock.lock_owned().await
+}
+
+// A playable file produced for a video
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Rendition {
+    pub name: String,
+    // Path relative to the converted directory
+    pub file_name: String,
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub audio_only: bool,
+}
+
+// Where and by whom a video was posted
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Posting {
+    pub guild_id: Option<u64>,
+    pub submitter_id: Option<u64>,
+}
+
+// Persistent information about a converted video
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct VideoMetadata {
+    pub id: String,
+    #[serde(default)]
+    pub source_url: Option<String>,
+    // Primary rendition first
+    #[serde(default)]
+    pub renditions: Vec<Rendition>,
+    #[serde(default)]
+    pub duration: Option<f64>,
+    // SHA-256 of the downloaded source file
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    #[serde(default)]
+    pub perceptual_hash: Option<String>,
+    // Other source IDs that resolved to this video
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    // Posted in a channel that isn't visible to everyone in the server
+    #[serde(default)]
+    pub private: bool,
+    // Discord server the video was first posted in
+    #[serde(default)]
+    pub guild_id: Option<u64>,
+    // Discord user who posted the video
+    #[serde(default)]
+    pub submitter_id: Option<u64>,
+    // Trace of the conversion, linked from requests that fetch the video
+    #[serde(default)]
+    pub traceparent: Option<String>,
+    // Packaged for adaptive streaming under {id}/hls/
+    #[serde(default)]
+    pub hls: bool,
+    // Postings after the first one, from reposts of the link and duplicates of the video
+    #[serde(default)]
+    pub postings: Vec<Posting>,
+}
+
+impl VideoMetadata {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn path(id: &str) -> PathBuf {
+        Path::new(&CONFIG.metadata_dir).join(format!("{}.json", id))
+    }
+
+    // Every stored video's metadata
+    pub async fn load_all() -> Result<Vec<Self>> {
+        let mut videos = Vec::new();
+        let mut entries = fs::read_dir(&CONFIG.metadata_dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            if let Ok(content) = fs::read(&path).await
+                && let Ok(metadata) = serde_json::from_slice(&content)
+            {
+                videos.push(metadata);
+            }
+        }
+
+        Ok(videos)
+    }
+
+    pub async fn load(id: &str) -> Option<Self> {
+        let content = fs::read(Self::path(id)).await.ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    // Store the metadata of a new video, existing ones are changed with update
+    pub async fn save(&self) -> Result<()> {
+        let _lock = lock(&self.id).await;
+        self.write().await
+    }
+
+    // Change the stored metadata of a video, reloaded under its lock so concurrent changes
+    // aren't lost. None if the video has no metadata
+    pub async fn update(id: &str, change: impl FnOnce(&mut Self)) -> Result<Option<Self>> {
+        let _lock = lock(id).await;
+        let Some(mut metadata) = Self::load(id).await else {
+            return Ok(None);
+        };
+        change(&mut metadata);
+        metadata.write().await?;
+
+        Ok(Some(metadata))
+    }
+
+    async fn write(&self) -> Result<()> {
+        let path = Self::path(&self.id);
+        let temp_path = path.with_extension(format!(
+            "json.{}.{}.tmp",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        // Write to a temporary file first so readers never see a partial file
+        if let Err(e) = fs::write(&temp_path, serde_json::to_vec_pretty(self)?).await {
+            fs::remove_file(&temp_path).await.ok();
+            return Err(e.into());
+        }
+        fs::rename(&temp_path, &path).await?;
+
+        Ok(())
+    }
+
+    // Remove the metadata along with the index entries pointing at it
+    pub async fn delete(&self) -> Result<()> {
+        let _lock = lock(&self.id).await;
+        if let Some(content_hash) = &self.content_hash {
+            remove_index("hash", content_hash, &self.id).await?;
+        }
+        for alias in &self.aliases {
+            remove_index("alias", alias, &self.id).await?;
+        }
+
+        fs::remove_file(Self::path(&self.id)).await?;
+        Ok(())
+    }
+
+    fn first_posting(&self) -> Posting {
+        Posting {
+            guild_id: self.guild_id,
+            submitter_id: self.submitter_id,
+        }
+    }
+
+    // Every posting of the video, the first one included
+    pub fn all_postings(&self) -> Vec<Posting> {
+        let mut postings = vec![self.first_posting()];
+        postings.extend(self.postings.iter().cloned());
+        postings
+    }
+
+    pub fn guild_ids(&self) -> Vec<u64> {
+        let mut guild_ids = Vec::new();
+        for id in self
+            .all_postings()
+            .iter()
+            .filter_map(|posting| posting.guild_id)
+        {
+            if !guild_ids.contains(&id) {
+                guild_ids.push(id);
+            }
+        }
+        guild_ids
+    }
+
+    pub fn submitter_ids(&self) -> Vec<u64> {
+        let mut submitter_ids = Vec::new();
+        for id in self
+            .all_postings()
+            .iter()
+            .filter_map(|posting| posting.submitter_id)
+        {
+            if !submitter_ids.contains(&id) {
+                submitter_ids.push(id);
+            }
+        }
+        submitter_ids
+    }
+
+    // Record another posting, a private one makes the whole video private. Returns whether
+    // anything changed and the metadata needs saving
+    pub fn add_posting(&mut self, origin: &Origin) -> bool {
+        let posting = Posting {
+            guild_id: origin.guild_id,
+            submitter_id: origin.submitter_id,
+        };
+        let known = self.all_postings().contains(&posting);
+        if known && (self.private || !origin.private) {
+            return false;
+        }
+
+        self.private |= origin.private;
+        if !known {
+            self.postings.push(posting);
+        }
+        true
+    }
+
+    // Drop matching postings, the first remaining one takes the place of the original
+    pub fn remove_postings(&mut self, matches: impl Fn(&Posting) -> bool) {
+        let mut remaining = self
+            .all_postings()
+            .into_iter()
+            .filter(|posting| !matches(posting));
+        let first = remaining.next();
+        self.guild_id = first.as_ref().and_then(|posting| posting.guild_id);
+        self.submitter_id = first.and_then(|posting| posting.submitter_id);
+        self.postings = remaining.collect();
+    }
+
+    // Video renditions ordered from the tallest to the shortest
+    pub fn video_renditions(&self) -> Vec<&Rendition> {
+        let mut renditions: Vec<_> = self
+            .renditions
+            .iter()
+            .filter(|rendition| !rendition.audio_only)
+            .collect();
+        renditions.sort_by_key(|rendition| std::cmp::Reverse(rendition.height.unwrap_or(0)));
+        renditions
+    }
+
+    // The tallest rendition that fits under the given height, or the smallest one
+    pub fn rendition_for_height(&self, max_height: u32) -> Option<&Rendition> {
+        let renditions = self.video_renditions();
+        renditions
+            .iter()
+            .find(|rendition| rendition.height.is_none_or(|height| height <= max_height))
+            .or(renditions.last())
+            .copied()
+    }
+}
+
+// Lookup files mapping content hashes and alias IDs to a stored video ID
+fn index_path(kind: &str, key: &str) -> PathBuf {
+    Path::new(&CONFIG.metadata_dir)
+        .join("index")
+        .join(format!("{}-{}", kind, key))
+}
+
+async fn read_index(kind: &str, key: &str) -> Option<String> {
+    let id = fs::read_to_string(index_path(kind, key)).await.ok()?;
+    Some(id.trim().to_string())
+}
+
+async fn write_index(kind: &str, key: &str, id: &str) -> Result<()> {
+    let path = index_path(kind, key);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    fs::write(path, id).await?;
+    Ok(())
+}
+
+// Remove an index entry unless it has since been pointed at another video
+async fn remove_index(kind: &str, key: &str, id: &str) -> Result<()> {
+    if read_index(kind, key).await.as_deref() == Some(id) {
+        fs::remove_file(index_path(kind, key)).await?;
+    }
+
+    Ok(())
+}
+
+// ID of the video converted from content with this hash
+pub async fn lookup_hash(hash: &str) -> Option<String> {
+    read_index("hash", hash).await
+}
+
+pub async fn record_hash(hash: &str, id: &str) -> Result<()> {
+    write_index("hash", hash, id).await
+}
+
+// ID of the video a source ID was deduplicated into
+pub async fn resolve_alias(alias: &str) -> Option<String> {
+    read_index("alias", alias).await
+}
+
+pub async fn record_alias(alias: &str, id: &str) -> Result<()> {
+    write_index("alias", alias, id).await
+}
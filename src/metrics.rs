@@ -0,0 +1,111 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGaugeVec, TextEncoder,
+};
+
+use crate::bot::services::probe::Rejection;
+
+lazy_static! {
+    pub static ref MESSAGES_MATCHED: IntCounter = register_int_counter!(
+        "cerebro_messages_matched_total",
+        "Discord messages containing a video link"
+    )
+    .unwrap();
+    pub static ref JOBS_QUEUED: IntCounter =
+        register_int_counter!("cerebro_jobs_queued_total", "Conversion jobs started").unwrap();
+    pub static ref JOBS_SUCCEEDED: IntCounter = register_int_counter!(
+        "cerebro_jobs_succeeded_total",
+        "Conversion jobs that finished successfully"
+    )
+    .unwrap();
+    pub static ref JOBS_FAILED: IntCounterVec = register_int_counter_vec!(
+        "cerebro_jobs_failed_total",
+        "Conversion jobs that failed, by reason",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref DOWNLOAD_BYTES: IntCounter = register_int_counter!(
+        "cerebro_download_bytes_total",
+        "Bytes downloaded from video sources"
+    )
+    .unwrap();
+    pub static ref DOWNLOAD_DURATION: Histogram = register_histogram!(
+        "cerebro_download_duration_seconds",
+        "Time spent downloading source videos",
+        exponential_buckets(0.1, 2.0, 12).unwrap()
+    )
+    .unwrap();
+    pub static ref ENCODE_DURATION: Histogram = register_histogram!(
+        "cerebro_ffmpeg_encode_duration_seconds",
+        "Wall time of the primary H264 encode",
+        exponential_buckets(0.5, 2.0, 14).unwrap()
+    )
+    .unwrap();
+    // Seconds of video encoded per second of wall time
+    pub static ref ENCODE_SPEED: Histogram = register_histogram!(
+        "cerebro_ffmpeg_speed_ratio",
+        "Ratio of video duration to encode time",
+        exponential_buckets(0.125, 2.0, 10).unwrap()
+    )
+    .unwrap();
+    pub static ref THUMBNAILS_GENERATED: IntCounter = register_int_counter!(
+        "cerebro_thumbnails_generated_total",
+        "Videos whose thumbnails and previews were generated"
+    )
+    .unwrap();
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "cerebro_http_requests_total",
+        "HTTP requests served, by route and status",
+        &["route", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_RESPONSE_BYTES: IntCounterVec = register_int_counter_vec!(
+        "cerebro_http_response_bytes_total",
+        "HTTP response body bytes served, by route",
+        &["route"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "cerebro_http_request_duration_seconds",
+        "Time to produce an HTTP response, by route",
+        &["route"]
+    )
+    .unwrap();
+    pub static ref STORAGE_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "cerebro_storage_bytes",
+        "Disk space used by the data directories",
+        &["directory"]
+    )
+    .unwrap();
+}
+
+// Coarse failure reason used as a metric label
+pub fn failure_reason(error: &anyhow::Error) -> &'static str {
+    if error.downcast_ref::<Rejection>().is_some() {
+        return "rejected";
+    }
+
+    let message = error.to_string();
+    if message.contains("timed out") {
+        "timeout"
+    } else if message.starts_with("Download failed") || error.is::<reqwest::Error>() {
+        "download"
+    } else if message.contains("ffmpeg") || message.starts_with("Failed to convert") {
+        "ffmpeg"
+    } else if message.starts_with("S3 request failed") {
+        "storage"
+    } else {
+        "other"
+    }
+}
+
+// All registered metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        tracing::error!("Failed to encode metrics: {:?}", e);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
@@ -0,0 +1,293 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::config::CONFIG;
+
+// Buckets tracked before refilled ones are forgotten
+const MAX_TRACKED: usize = 10_000;
+
+// Allow bursts of `capacity`, refilled at `capacity` per `period`
+#[derive(Clone, Copy)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    // Parse "{capacity}/{seconds}", None if malformed and Some(None) when a capacity of 0 disables the limit
+    pub fn parse(value: &str) -> Option<Option<Self>> {
+        let (capacity, seconds) = value.split_once('/')?;
+        let capacity: u32 = capacity.trim().parse().ok()?;
+        let seconds: u64 = seconds.trim().parse().ok()?;
+        if capacity == 0 {
+            return Some(None);
+        }
+        if seconds == 0 {
+            return None;
+        }
+
+        Some(Some(Self {
+            capacity,
+            period: Duration::from_secs(seconds),
+        }))
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token buckets keyed by whoever is being limited
+pub struct RateLimiter<K> {
+    limit: Option<RateLimit>,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Lock the buckets so several checks and takes happen atomically
+    async fn lock(&self) -> LockedLimiter<'_, K> {
+        LockedLimiter {
+            limit: self.limit,
+            buckets: self.buckets.lock().await,
+        }
+    }
+
+    // Take a token if one is available, otherwise the time until one is
+    pub async fn acquire(&self, key: &K) -> Result<(), Duration> {
+        let mut locked = self.lock().await;
+        match locked.wait_time(key) {
+            Some(wait) => Err(wait),
+            None => {
+                locked.take(key);
+                Ok(())
+            }
+        }
+    }
+}
+
+struct LockedLimiter<'a, K> {
+    limit: Option<RateLimit>,
+    buckets: MutexGuard<'a, HashMap<K, Bucket>>,
+}
+
+impl<K: Eq + Hash + Clone> LockedLimiter<'_, K> {
+    // Time until a token is available for the key, None if one is available now
+    fn wait_time(&mut self, key: &K) -> Option<Duration> {
+        let limit = self.limit?;
+        let tokens = refill(&mut self.buckets, limit, key, Instant::now());
+        if tokens >= 1.0 {
+            return None;
+        }
+
+        let per_token = limit.period.as_secs_f64() / limit.capacity as f64;
+        Some(Duration::from_secs_f64((1.0 - tokens) * per_token))
+    }
+
+    // Only called after wait_time found a token while still holding the lock
+    fn take(&mut self, key: &K) {
+        if let Some(bucket) = self.buckets.get_mut(key) {
+            bucket.tokens -= 1.0;
+        }
+    }
+}
+
+// Bring a bucket up to date and return its tokens
+fn refill<K: Eq + Hash + Clone>(
+    buckets: &mut HashMap<K, Bucket>,
+    limit: RateLimit,
+    key: &K,
+    now: Instant,
+) -> f64 {
+    let capacity = limit.capacity as f64;
+    let rate = capacity / limit.period.as_secs_f64();
+
+    // Full buckets behave the same as missing ones
+    if buckets.len() >= MAX_TRACKED && !buckets.contains_key(key) {
+        buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity
+        });
+    }
+
+    let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+        tokens: capacity,
+        updated: now,
+    });
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+    bucket.updated = now;
+    bucket.tokens
+}
+
+// Conversions throttled because a limit was reached
+#[derive(Debug)]
+pub struct Throttled {
+    pub scope: &'static str,
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for Throttled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.retry_after.as_secs().max(1);
+        let wait = if seconds < 120 {
+            format!("{} seconds", seconds)
+        } else {
+            format!("{} minutes", seconds.div_ceil(60))
+        };
+        write!(
+            f,
+            "{} started too many conversions recently, try again in {}",
+            self.scope, wait
+        )
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+// Take a conversion token from the user, channel and guild, or none of them if any is empty
+pub async fn acquire_conversion(
+    user_id: u64,
+    channel_id: u64,
+    guild_id: Option<u64>,
+) -> Result<(), Throttled> {
+    if CONFIG.admin_user_ids.contains(&user_id) {
+        return Ok(());
+    }
+
+    // Always locked in the same order, and held together so concurrent messages can't
+    // all pass the check before any takes a token
+    let mut limits = vec![
+        (USER_LIMITER.lock().await, user_id, "you've"),
+        (CHANNEL_LIMITER.lock().await, channel_id, "this channel has"),
+    ];
+    if let Some(guild_id) = guild_id {
+        limits.push((GUILD_LIMITER.lock().await, guild_id, "this server has"));
+    }
+
+    for (locked, key, scope) in &mut limits {
+        if let Some(retry_after) = locked.wait_time(key) {
+            return Err(Throttled { scope, retry_after });
+        }
+    }
+    for (locked, key, _) in &mut limits {
+        locked.take(key);
+    }
+
+    Ok(())
+}
+
+lazy_static! {
+    static ref USER_LIMITER: RateLimiter<u64> = RateLimiter::new(CONFIG.user_rate_limit);
+    static ref CHANNEL_LIMITER: RateLimiter<u64> = RateLimiter::new(CONFIG.channel_rate_limit);
+    static ref GUILD_LIMITER: RateLimiter<u64> = RateLimiter::new(CONFIG.guild_rate_limit);
+    pub static ref IP_LIMITER: RateLimiter<IpAddr> = RateLimiter::new(CONFIG.web_rate_limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(capacity: u32, seconds: u64) -> RateLimit {
+        RateLimit {
+            capacity,
+            period: Duration::from_secs(seconds),
+        }
+    }
+
+    #[test]
+    fn parses_limits() {
+        let parsed = RateLimit::parse("5/600").unwrap().unwrap();
+        assert_eq!(parsed.capacity, 5);
+        assert_eq!(parsed.period, Duration::from_secs(600));
+
+        let parsed = RateLimit::parse(" 20 / 60 ").unwrap().unwrap();
+        assert_eq!(parsed.capacity, 20);
+        assert_eq!(parsed.period, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn zero_capacity_disables_the_limit() {
+        assert!(RateLimit::parse("0/0").unwrap().is_none());
+        assert!(RateLimit::parse("0/600").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_limits() {
+        assert!(RateLimit::parse("5").is_none());
+        assert!(RateLimit::parse("5/0").is_none());
+        assert!(RateLimit::parse("-1/60").is_none());
+        assert!(RateLimit::parse("five/60").is_none());
+        assert!(RateLimit::parse("5/1.5").is_none());
+    }
+
+    #[test]
+    fn refills_over_time_up_to_capacity() {
+        let limit = limit(2, 10);
+        let mut buckets = HashMap::new();
+        let start = Instant::now();
+
+        assert_eq!(refill(&mut buckets, limit, &1, start), 2.0);
+        buckets.get_mut(&1).unwrap().tokens = 0.0;
+
+        let tokens = refill(&mut buckets, limit, &1, start + Duration::from_secs(5));
+        assert!((tokens - 1.0).abs() < 1e-9);
+        let tokens = refill(&mut buckets, limit, &1, start + Duration::from_secs(60));
+        assert_eq!(tokens, 2.0);
+    }
+
+    #[tokio::test]
+    async fn throttles_until_a_token_is_refilled() {
+        let limiter = RateLimiter::new(Some(limit(2, 60)));
+        assert!(limiter.acquire(&1).await.is_ok());
+        assert!(limiter.acquire(&1).await.is_ok());
+
+        let wait = limiter.acquire(&1).await.unwrap_err();
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+
+        // Other keys have their own buckets
+        assert!(limiter.acquire(&2).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn disabled_limiter_never_throttles() {
+        let limiter = RateLimiter::new(None);
+        for _ in 0..100 {
+            assert!(limiter.acquire(&1).await.is_ok());
+        }
+    }
+
+    #[test]
+    fn forgets_full_buckets_once_too_many_are_tracked() {
+        let limit = limit(2, 10);
+        let now = Instant::now();
+        let mut buckets = HashMap::new();
+        for key in 0..MAX_TRACKED {
+            buckets.insert(
+                key,
+                Bucket {
+                    tokens: 2.0,
+                    updated: now,
+                },
+            );
+        }
+        buckets.get_mut(&0).unwrap().tokens = 0.0;
+
+        refill(&mut buckets, limit, &MAX_TRACKED, now);
+
+        // Only the drained bucket and the new one are left
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[&0].tokens, 0.0);
+        assert!(buckets.contains_key(&MAX_TRACKED));
+    }
+}
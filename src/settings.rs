@@ -0,0 +1,195 @@
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::config::CONFIG;
+
+// Reactions added to the message containing the link
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReactionStyle {
+    // Hourglass while converting and a cross on failure
+    #[default]
+    Full,
+    // Only a cross on failure
+    Minimal,
+    None,
+}
+
+impl ReactionStyle {
+    pub const ALL: [Self; 3] = [Self::Full, Self::Minimal, Self::None];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Minimal => "minimal",
+            Self::None => "none",
+        }
+    }
+}
+
+// Where the converted video is posted
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyMode {
+    // A new message in the same channel
+    #[default]
+    Channel,
+    // A reply referencing the message containing the link
+    Reply,
+    // A thread started from the message containing the link
+    Thread,
+}
+
+impl ReplyMode {
+    pub const ALL: [Self; 3] = [Self::Channel, Self::Reply, Self::Thread];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Channel => "channel",
+            Self::Reply => "reply",
+            Self::Thread => "thread",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+// Settings a guild manages for itself through slash commands
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct GuildSettings {
+    // The guild opted out of having its messages processed
+    #[serde(default)]
+    pub disabled: bool,
+    // Channels to listen in, every channel if empty
+    #[serde(default)]
+    pub allowed_channels: Vec<u64>,
+    #[serde(default)]
+    pub denied_channels: Vec<u64>,
+    #[serde(default)]
+    pub reactions: ReactionStyle,
+    // REPLY_MODE is used if unset
+    #[serde(default)]
+    pub reply_mode: Option<ReplyMode>,
+    // Name of an FFMPEG_PROFILES entry, FFMPEG_ARGS is used if unset
+    #[serde(default)]
+    pub profile: Option<String>,
+    // Hide the embed of the message containing the link once the video is posted,
+    // SUPPRESS_EMBEDS is used if unset
+    #[serde(default)]
+    pub suppress_embeds: Option<bool>,
+    // Longest accepted video in seconds, only lowers MAX_VIDEO_DURATION
+    #[serde(default)]
+    pub max_duration: Option<f64>,
+    #[serde(default)]
+    pub disabled_providers: Vec<String>,
+}
+
+impl GuildSettings {
+    pub fn provider_enabled(&self, provider: &str) -> bool {
+        !self.disabled_providers.iter().any(|name| name == provider)
+    }
+
+    pub fn reply_mode(&self) -> ReplyMode {
+        self.reply_mode.unwrap_or(CONFIG.reply_mode)
+    }
+
+    pub fn suppress_embeds(&self) -> bool {
+        self.suppress_embeds.unwrap_or(CONFIG.suppress_embeds)
+    }
+
+    pub fn max_duration(&self) -> f64 {
+        self.max_duration
+            .map_or(CONFIG.max_video_duration, |duration| {
+                duration.min(CONFIG.max_video_duration)
+            })
+    }
+
+    // FFMPEG arguments template of the chosen profile
+    pub fn ffmpeg_args(&self) -> &'static str {
+        match &self.profile {
+            Some(name) => match CONFIG.ffmpeg_profiles.get(name) {
+                Some(args) => args,
+                None => {
+                    warn!("Unknown encoding profile {}, using the default", name);
+                    &CONFIG.ffmpeg_args
+                }
+            },
+            None => &CONFIG.ffmpeg_args,
+        }
+    }
+}
+
+// Settings of every guild that changed them, persisted as a single file
+pub struct SettingsStore {
+    guilds: RwLock<HashMap<u64, GuildSettings>>,
+}
+
+impl SettingsStore {
+    // Forced during startup, starting without the file would overwrite it on the next change
+    fn load() -> Self {
+        let guilds = match std::fs::read(&CONFIG.guild_settings_file) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                panic!("Failed to parse {}: {}", CONFIG.guild_settings_file, e)
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => panic!("Failed to read {}: {}", CONFIG.guild_settings_file, e),
+        };
+
+        Self {
+            guilds: RwLock::new(guilds),
+        }
+    }
+
+    // Settings of the guild a message came from, the defaults for direct messages
+    pub async fn for_guild(&self, guild_id: Option<u64>) -> GuildSettings {
+        match guild_id {
+            Some(guild_id) => self.get(guild_id).await,
+            None => GuildSettings::default(),
+        }
+    }
+
+    pub async fn get(&self, guild_id: u64) -> GuildSettings {
+        self.guilds
+            .read()
+            .await
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Change a guild's settings and persist them, returning the updated settings
+    pub async fn update(
+        &self,
+        guild_id: u64,
+        change: impl FnOnce(&mut GuildSettings),
+    ) -> Result<GuildSettings> {
+        let mut guilds = self.guilds.write().await;
+
+        // The change is applied to a copy and only kept once it's saved
+        let mut changed = guilds.clone();
+        let settings = changed.entry(guild_id).or_default();
+        change(settings);
+        let updated = settings.clone();
+
+        // Write to a temporary file first so a crash never leaves a partial file
+        let path = Path::new(&CONFIG.guild_settings_file);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_vec_pretty(&changed)?).await?;
+        fs::rename(&temp_path, path).await?;
+
+        *guilds = changed;
+        Ok(updated)
+    }
+}
+
+lazy_static! {
+    pub static ref SETTINGS: SettingsStore = SettingsStore::load();
+}
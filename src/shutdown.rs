@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serenity::http::Http;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::bot::commands::YliProxyHandler;
+use crate::bot::services::pipeline::run_job;
+use crate::config::CONFIG;
+use crate::health::HEALTH;
+use crate::jobs::{Origin, JOBS};
+
+// How often draining checks whether the in-flight work has finished
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Requests that are being handled, from accepting the link to the reply
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+// Held while a request is being handled so shutdown waits for it
+pub struct InFlight;
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Accept new work, None once the application is shutting down
+pub fn accept() -> Option<InFlight> {
+    // Counted before checking so draining can't miss work accepted at the same time
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    let guard = InFlight;
+
+    if HEALTH.is_shutting_down() {
+        return None;
+    }
+    Some(guard)
+}
+
+// A job that was still running when the application stopped
+#[derive(Serialize, Deserialize)]
+struct PendingJob {
+    id: String,
+    source_url: String,
+    origin: Origin,
+}
+
+// Resolve once SIGINT or SIGTERM is received
+pub async fn wait_for_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result.expect("Failed to listen for Ctrl+C");
+            info!("SIGINT received, shutting down");
+        }
+        _ = terminate.recv() => info!("SIGTERM received, shutting down"),
+    }
+}
+
+// Stop accepting work and wait for running jobs. The unfinished ones are saved right away
+// and the file is kept up to date as they finish, in case the process is killed first
+pub async fn drain() {
+    HEALTH.begin_shutdown();
+
+    let deadline = Instant::now() + CONFIG.shutdown_timeout;
+    let mut saved = None;
+    loop {
+        let pending: Vec<_> = JOBS
+            .list()
+            .await
+            .into_iter()
+            .map(|job| PendingJob {
+                id: job.id,
+                source_url: job.source_url,
+                origin: job.origin,
+            })
+            .collect();
+        let ids: Vec<_> = pending.iter().map(|job| job.id.clone()).collect();
+        if saved.as_ref() != Some(&ids) {
+            if saved.is_none() && !ids.is_empty() {
+                info!(
+                    "Waiting up to {:?} for {} running jobs to finish",
+                    CONFIG.shutdown_timeout,
+                    ids.len()
+                );
+            }
+            if let Err(e) = save_pending(&pending).await {
+                error!("Failed to save unfinished jobs: {:?}", e);
+            }
+            saved = Some(ids);
+        }
+
+        if IN_FLIGHT.load(Ordering::SeqCst) == 0 {
+            break;
+        }
+        if Instant::now() >= deadline {
+            warn!(
+                "Shutdown timeout of {:?} reached with work still running",
+                CONFIG.shutdown_timeout
+            );
+            break;
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+
+    if let Some(ids) = saved
+        && !ids.is_empty()
+    {
+        info!("Saved {} unfinished jobs to resume later", ids.len());
+    }
+}
+
+// Replace the saved jobs, removing the file once none are left
+async fn save_pending(pending: &[PendingJob]) -> Result<()> {
+    let path = Path::new(&CONFIG.pending_jobs_file);
+    if pending.is_empty() {
+        return match fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+
+    // Written to a temporary file first so a kill mid-write never leaves a partial file
+    let temp_path = path.with_extension("json.tmp");
+    let json = serde_json::to_vec_pretty(pending)?;
+    fs::write(&temp_path, json)
+        .await
+        .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+    fs::rename(&temp_path, path)
+        .await
+        .with_context(|| format!("Failed to write {}", CONFIG.pending_jobs_file))
+}
+
+// Restart the jobs that were interrupted by the last shutdown
+pub async fn resume_pending() {
+    let json = match fs::read(&CONFIG.pending_jobs_file).await {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("Failed to read {}: {:?}", CONFIG.pending_jobs_file, e);
+            return;
+        }
+    };
+    // Removed first so a job that brings the application down isn't retried forever
+    if let Err(e) = fs::remove_file(&CONFIG.pending_jobs_file).await {
+        error!("Failed to remove {}: {:?}", CONFIG.pending_jobs_file, e);
+    }
+
+    let pending: Vec<PendingJob> = match serde_json::from_slice(&json) {
+        Ok(pending) => pending,
+        Err(e) => {
+            error!("Failed to parse {}: {:?}", CONFIG.pending_jobs_file, e);
+            return;
+        }
+    };
+
+    // The message handlers are gone, so requesters are answered over plain HTTP
+    let http = Arc::new(Http::new(&CONFIG.discord_token));
+    for job in pending {
+        let Some(in_flight) = accept() else {
+            return;
+        };
+
+        info!("Resuming job {} interrupted by the last shutdown", job.id);
+        let http = http.clone();
+        tokio::spawn(async move {
+            let _in_flight = in_flight;
+            let result = run_job(&job.source_url, &job.id, job.origin.clone()).await;
+            if let Err(e) = &result {
+                error!("Resumed job {} failed: {:?}", job.id, e);
+            }
+            YliProxyHandler::reply_resumed(&http, &job.origin, result).await;
+        });
+    }
+}
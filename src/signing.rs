@@ -0,0 +1,298 @@
+use actix_files::PathBufWrap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::CONFIG;
+
+// Suffixes of generated thumbnail and preview files, see the web thumbnails module
+const THUMBNAIL_SUFFIXES: [&str; 4] = ["-poster", "-preview", "-sprite", "-seek"];
+
+fn mac(secret: &str, path: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}\n{}", path, expires).as_bytes());
+    mac
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Append an expiring signature to a URL for the given server path, if signing is enabled
+pub fn sign_url(url: &str, path: &str) -> String {
+    let Some(secret) = &CONFIG.url_signing_secret else {
+        return url.to_string();
+    };
+
+    signed_url(secret, url, path, now() + CONFIG.url_signing_expiry)
+}
+
+fn signed_url(secret: &str, url: &str, path: &str, expires: u64) -> String {
+    let signature: String = mac(secret, scope(path), expires)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}expires={}&signature={}",
+        url, separator, expires, signature
+    )
+}
+
+// Path a signature covers. Files in a video's HLS directory share the signature of its
+// playlist, which the player passes on to the renditions and segments it lists
+fn scope(path: &str) -> &str {
+    if let Some((_, rest)) = path.trim_start_matches('/').split_once('/')
+        && rest.starts_with("hls/")
+    {
+        return &path[..path.len() - rest.len() + "hls/".len()];
+    }
+    path
+}
+
+// Whether the query string carries any signature parameters
+pub fn has_signature(query: &str) -> bool {
+    query
+        .split('&')
+        .any(|param| param.starts_with("signature="))
+}
+
+// Check a request path against the expiry and signature in its query string
+pub fn verify(path: &str, query: &str) -> bool {
+    let Some(secret) = &CONFIG.url_signing_secret else {
+        return false;
+    };
+
+    verify_signature(secret, path, query, now())
+}
+
+fn verify_signature(secret: &str, path: &str, query: &str, now: u64) -> bool {
+    let mut expires = None;
+    let mut signature = None;
+    for param in query.split('&') {
+        match param.split_once('=') {
+            Some(("expires", value)) => expires = value.parse::<u64>().ok(),
+            Some(("signature", value)) => signature = decode_hex(value),
+            _ => {}
+        }
+    }
+
+    let (Some(expires), Some(signature)) = (expires, signature) else {
+        return false;
+    };
+    if expires < now {
+        return false;
+    }
+
+    mac(secret, scope(path), expires)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+// Whether a normalised request path is a published video file, these always need a signed
+// link when signing is enabled. Thumbnails and previews are shown on the public index
+pub fn is_video_file(path: &str) -> bool {
+    if path.starts_with("/thumbs/") {
+        return false;
+    }
+
+    let extension = path.rsplit_once('.').map(|(_, extension)| extension);
+    matches!(extension, Some("mp4" | "m4a" | "m3u8" | "ts"))
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Percent-decode and normalise a request path the same way the file server does before
+// serving it, None if it doesn't decode or the file server would refuse it
+pub fn normalize_path(path: &str) -> Option<String> {
+    let path = PathBufWrap::parse_path(path, false).ok()?;
+    let segments: Vec<_> = path
+        .as_ref()
+        .iter()
+        .map(|segment| segment.to_str())
+        .collect::<Option<_>>()?;
+    Some(format!("/{}", segments.join("/")))
+}
+
+// ID of the video a normalised request path belongs to, if any
+pub fn video_id(path: &str) -> Option<&str> {
+    let path = path.trim_start_matches('/');
+
+    if let Some(id) = path.strip_prefix("watch/") {
+        return Some(id).filter(|id| !id.is_empty());
+    }
+
+    if let Some(name) = path.strip_prefix("thumbs/") {
+        let stem = name.split('.').next().unwrap_or_default();
+        let id = THUMBNAIL_SUFFIXES
+            .iter()
+            .find_map(|suffix| stem.strip_suffix(suffix))
+            .unwrap_or(stem);
+        return Some(id).filter(|id| !id.is_empty());
+    }
+
+    // Primary files live at {id}.mp4, renditions and HLS under {id}/
+    let first = path.split('/').next().unwrap_or_default();
+    let id = first.strip_suffix(".mp4").unwrap_or(first);
+    Some(id).filter(|id| !id.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+    const NOW: u64 = 1_700_000_000;
+
+    // Query string of a link signed for the path, expiring after the given seconds
+    fn signed_query(path: &str, valid_for: u64) -> String {
+        let url = signed_url(SECRET, path, path, NOW + valid_for);
+        url.split_once('?').unwrap().1.to_string()
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        let query = signed_query("/abc.mp4", 60);
+        assert!(has_signature(&query));
+        assert!(verify_signature(SECRET, "/abc.mp4", &query, NOW));
+        assert!(verify_signature(SECRET, "/abc.mp4", &query, NOW + 60));
+    }
+
+    #[test]
+    fn rejects_expired_signature() {
+        let query = signed_query("/abc.mp4", 60);
+        assert!(!verify_signature(SECRET, "/abc.mp4", &query, NOW + 61));
+    }
+
+    #[test]
+    fn rejects_tampered_signature() {
+        let query = signed_query("/abc.mp4", 60);
+
+        let extended = query.replace(
+            &format!("expires={}", NOW + 60),
+            &format!("expires={}", NOW + 6000),
+        );
+        assert!(!verify_signature(SECRET, "/abc.mp4", &extended, NOW));
+
+        let (rest, signature) = query.rsplit_once('=').unwrap();
+        let flipped = if signature.starts_with('0') { "1" } else { "0" };
+        let tampered = format!("{}={}{}", rest, flipped, &signature[1..]);
+        assert!(!verify_signature(SECRET, "/abc.mp4", &tampered, NOW));
+
+        assert!(!verify_signature(
+            SECRET,
+            "/abc.mp4",
+            "expires=1&signature=zz",
+            NOW
+        ));
+        assert!(!verify_signature(SECRET, "/abc.mp4", "", NOW));
+        assert!(!verify_signature("other-secret", "/abc.mp4", &query, NOW));
+    }
+
+    #[test]
+    fn rejects_wrong_path() {
+        let query = signed_query("/abc.mp4", 60);
+        assert!(!verify_signature(SECRET, "/abd.mp4", &query, NOW));
+        assert!(!verify_signature(SECRET, "/abc/720p.mp4", &query, NOW));
+    }
+
+    #[test]
+    fn playlist_signature_covers_its_hls_directory() {
+        let query = signed_query("/abc/hls/master.m3u8", 60);
+        assert!(verify_signature(
+            SECRET,
+            "/abc/hls/720p/index.m3u8",
+            &query,
+            NOW
+        ));
+        assert!(verify_signature(
+            SECRET,
+            "/abc/hls/720p/segment000.ts",
+            &query,
+            NOW
+        ));
+        assert!(!verify_signature(SECRET, "/abc/720p.mp4", &query, NOW));
+        assert!(!verify_signature(
+            SECRET,
+            "/abd/hls/master.m3u8",
+            &query,
+            NOW
+        ));
+    }
+
+    #[test]
+    fn appends_to_existing_query() {
+        let url = signed_url(SECRET, "/abc.mp4?download=1", "/abc.mp4", NOW);
+        assert!(url.starts_with("/abc.mp4?download=1&expires="));
+    }
+
+    #[test]
+    fn recognises_video_files() {
+        assert!(is_video_file("/abc.mp4"));
+        assert!(is_video_file("/abc/720p.mp4"));
+        assert!(is_video_file("/abc/audio.m4a"));
+        assert!(is_video_file("/abc/hls/master.m3u8"));
+        assert!(is_video_file("/abc/hls/720p/segment000.ts"));
+        assert!(!is_video_file("/thumbs/abc-preview.mp4"));
+        assert!(!is_video_file("/watch/abc"));
+        assert!(!is_video_file("/static/hls.min.js"));
+    }
+
+    #[test]
+    fn normalizes_paths_like_the_file_server() {
+        assert_eq!(normalize_path("/abc.mp4").as_deref(), Some("/abc.mp4"));
+        assert_eq!(
+            normalize_path("//abc//720p.mp4").as_deref(),
+            Some("/abc/720p.mp4")
+        );
+        assert_eq!(normalize_path("/a%62c.mp4").as_deref(), Some("/abc.mp4"));
+        // Encoded or not, .. is resolved so the signature is checked for the file served
+        assert_eq!(
+            normalize_path("/abc/../def.mp4").as_deref(),
+            Some("/def.mp4")
+        );
+        assert_eq!(
+            normalize_path("/abc/%2e%2e/def.mp4").as_deref(),
+            Some("/def.mp4")
+        );
+        assert_eq!(
+            normalize_path("/%2E%2E/%2e%2e/def.mp4").as_deref(),
+            Some("/def.mp4")
+        );
+        // The file server refuses hidden files and malformed encodings
+        assert_eq!(normalize_path("/.hidden.mp4"), None);
+        assert_eq!(normalize_path("/abc/%2ehidden.mp4"), None);
+        assert_eq!(normalize_path("/abc%ff.mp4"), None);
+    }
+
+    #[test]
+    fn extracts_video_ids() {
+        assert_eq!(video_id("/abc.mp4"), Some("abc"));
+        assert_eq!(video_id("/abc/720p.mp4"), Some("abc"));
+        assert_eq!(video_id("/abc/hls/master.m3u8"), Some("abc"));
+        assert_eq!(video_id("/watch/abc"), Some("abc"));
+        assert_eq!(video_id("/thumbs/abc.jpg"), Some("abc"));
+        assert_eq!(video_id("/thumbs/abc-poster.webp"), Some("abc"));
+        assert_eq!(video_id("/thumbs/abc-preview.mp4"), Some("abc"));
+        assert_eq!(video_id("/thumbs/abc-sprite.jpg"), Some("abc"));
+        assert_eq!(video_id("/thumbs/abc-seek.vtt"), Some("abc"));
+        assert_eq!(video_id("/watch/"), None);
+        assert_eq!(video_id("/"), None);
+    }
+}
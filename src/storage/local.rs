@@ -0,0 +1,80 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::config::CONFIG;
+use crate::storage::Storage;
+
+// Files stay in the converted directory and are served by the web server
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new() -> Self {
+        Self {
+            root: PathBuf::from(&CONFIG.converted_dir),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, path: &Path) -> Result<()> {
+        let destination = self.root.join(key);
+
+        // Files produced in the converted directory are already in place
+        if destination != path {
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::copy(path, &destination).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn fetch(&self, key: &str, path: &Path) -> Result<()> {
+        let source = self.root.join(key);
+        if source != path {
+            fs::copy(source, path).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        fs::remove_file(self.root.join(key)).await?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut pending = vec![self.root.clone()];
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+
+                let key = path
+                    .strip_prefix(&self.root)?
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn url(&self, key: &str) -> Result<String> {
+        Ok(format!("{}/{}", CONFIG.public_url, key))
+    }
+}
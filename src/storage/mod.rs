@@ -0,0 +1,141 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::config::CONFIG;
+
+pub mod local;
+pub mod s3;
+
+// Backend holding converted media, keyed by paths relative to the converted directory
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // Store a local file under a key
+    async fn put(&self, key: &str, path: &Path) -> Result<()>;
+
+    // Download a key into a local file, streaming it to disk
+    async fn fetch(&self, key: &str, path: &Path) -> Result<()>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    // Keys starting with a prefix
+    async fn list(&self, prefix: &str) -> Result<Vec<String>>;
+
+    // URL clients can fetch a key from, signed if the backend requires it
+    async fn url(&self, key: &str) -> Result<String>;
+}
+
+fn create_storage() -> Box<dyn Storage> {
+    match CONFIG.storage_backend.as_str() {
+        "local" => Box::new(local::LocalStorage::new()),
+        "s3" => Box::new(s3::S3Storage::new()),
+        other => panic!("Unknown STORAGE_BACKEND: {}", other),
+    }
+}
+
+lazy_static! {
+    pub static ref STORAGE: Box<dyn Storage> = create_storage();
+}
+
+// Whether clients may be sent to a key: primary videos, renditions and HLS playlists and
+// segments. Anything else in the bucket stays private
+pub fn is_published_key(key: &str) -> bool {
+    let segments: Vec<_> = key.split('/').collect();
+    if segments
+        .iter()
+        .any(|segment| segment.is_empty() || segment.starts_with('.'))
+    {
+        return false;
+    }
+
+    let has_extension = |name: &str, extensions: &[&str]| {
+        name.rsplit_once('.')
+            .is_some_and(|(_, extension)| extensions.contains(&extension))
+    };
+    match segments.as_slice() {
+        [file] => has_extension(file, &["mp4"]),
+        [_, "hls", .., file] => has_extension(file, &["m3u8", "ts"]),
+        [_, file] => has_extension(file, &["mp4", "m4a"]),
+        _ => false,
+    }
+}
+
+// Store every file below a directory, keys are relative to the converted directory
+pub async fn put_dir(dir: &Path) -> Result<()> {
+    let mut pending: Vec<PathBuf> = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                pending.push(path);
+            } else {
+                STORAGE.put(&key_for(&path)?, &path).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Remote backends hold the published files, so local copies are only kept while a video
+// is being processed. HLS stays local unless the bucket is public, as presigned playlists
+// can't reference their segments
+pub async fn release_local(id: &str) {
+    if CONFIG.storage_backend == "local" {
+        return;
+    }
+
+    let converted_dir = Path::new(&CONFIG.converted_dir);
+    fs::remove_file(converted_dir.join(format!("{}.mp4", id)))
+        .await
+        .ok();
+
+    let Ok(mut entries) = fs::read_dir(converted_dir.join(id)).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if entry.file_name() == "hls" && CONFIG.s3_public_url.is_none() {
+            continue;
+        }
+        match entry.file_type().await {
+            Ok(file_type) if file_type.is_dir() => fs::remove_dir_all(entry.path()).await.ok(),
+            _ => fs::remove_file(entry.path()).await.ok(),
+        };
+    }
+}
+
+// Storage key of a file inside the converted directory
+pub fn key_for(path: &Path) -> Result<String> {
+    let relative = path.strip_prefix(&CONFIG.converted_dir)?;
+    Ok(relative.to_string_lossy().replace('\\', "/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publishes_only_video_files() {
+        assert!(is_published_key("abc.mp4"));
+        assert!(is_published_key("abc/720p.mp4"));
+        assert!(is_published_key("abc/audio.m4a"));
+        assert!(is_published_key("abc/hls/master.m3u8"));
+        assert!(is_published_key("abc/hls/720p/index.m3u8"));
+        assert!(is_published_key("abc/hls/720p/segment000.ts"));
+
+        assert!(!is_published_key(""));
+        assert!(!is_published_key("abc.json"));
+        assert!(!is_published_key("index/hash-abc"));
+        assert!(!is_published_key("metadata/abc.json"));
+        assert!(!is_published_key("abc/hls/notes.txt"));
+        assert!(!is_published_key("abc/hls.mp4/x.ts"));
+        assert!(!is_published_key("abc/other/720p.mp4"));
+        assert!(!is_published_key("../abc.mp4"));
+        assert!(!is_published_key("abc//720p.mp4"));
+        assert!(!is_published_key(".hidden.mp4"));
+    }
+}
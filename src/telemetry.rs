@@ -0,0 +1,83 @@
+use opentelemetry::trace::{SpanContext, TraceContextExt, TracerProvider};
+use opentelemetry::{global, Context};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::CONFIG;
+
+// Set up log output, and span export when an OTLP endpoint is configured
+pub fn init() -> Option<SdkTracerProvider> {
+    let filter = EnvFilter::try_new(&CONFIG.log_filter).expect("RUST_LOG must be a valid filter");
+
+    let output = tracing_subscriber::fmt::layer();
+    let output = match CONFIG.log_format.as_str() {
+        "pretty" => output.boxed(),
+        // Every line carries the fields of its spans, such as the job it belongs to
+        "json" => output.json().with_span_list(true).boxed(),
+        other => panic!("Unknown LOG_FORMAT: {}", other),
+    };
+
+    let provider = CONFIG.otlp_endpoint.as_ref().map(|endpoint| {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()
+            .expect("Failed to create the OTLP span exporter");
+
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(CONFIG.otlp_service_name.clone())
+                    .build(),
+            )
+            .build()
+    });
+    let export = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("cerebro")));
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(export)
+        .init();
+
+    provider
+}
+
+pub fn enabled() -> bool {
+    CONFIG.otlp_endpoint.is_some()
+}
+
+// W3C traceparent of the current span, stored so later requests can be linked back to it
+pub fn current_traceparent() -> Option<String> {
+    if !enabled() {
+        return None;
+    }
+
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier.remove("traceparent")
+}
+
+// Context of a span from its W3C traceparent
+pub fn parse_traceparent(traceparent: &str) -> Context {
+    let carrier = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+    global::get_text_map_propagator(|propagator| propagator.extract(&carrier))
+}
+
+pub fn span_context(traceparent: &str) -> SpanContext {
+    parse_traceparent(traceparent).span().span_context().clone()
+}
@@ -0,0 +1,342 @@
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::audit::{self, AuditEntry};
+use crate::bot::services::pipeline::{build_derived, run_job};
+use crate::config::CONFIG;
+use crate::jobs::JOBS;
+use crate::library::{delete_video, remove_thumbnails};
+use crate::metadata::VideoMetadata;
+use crate::metrics::STORAGE_BYTES;
+use crate::shutdown;
+use crate::storage::STORAGE;
+use crate::web::auth::{current_session, login_enabled, Session};
+use crate::web::handlers::{html_escape, valid_id};
+use crate::web::models::ThumbnailCache;
+use crate::web::thumbnails::{thumbnail_path, ThumbnailFormat, ThumbnailSize};
+use crate::web::worker::ThumbnailWorker;
+
+// Let only logged in admins through, everyone else gets the response to send instead
+async fn require_admin(req: &HttpRequest) -> Result<Session, HttpResponse> {
+    if !login_enabled() || CONFIG.admin_user_ids.is_empty() {
+        return Err(HttpResponse::NotFound().finish());
+    }
+
+    match current_session(req).await {
+        Some(session) if CONFIG.admin_user_ids.contains(&session.user_id) => Ok(session),
+        Some(_) => Err(HttpResponse::Forbidden().finish()),
+        None => Err(HttpResponse::Found()
+            .insert_header((header::LOCATION, "/auth/login"))
+            .finish()),
+    }
+}
+
+fn back_to_dashboard() -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/admin"))
+        .finish()
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn ago(time: SystemTime) -> String {
+    let seconds = time.elapsed().unwrap_or_default().as_secs();
+    match seconds {
+        0..60 => format!("{}s ago", seconds),
+        60..3600 => format!("{}m ago", seconds / 60),
+        _ => format!("{}h ago", seconds / 3600),
+    }
+}
+
+fn action(id: &str, action: &str, label: &str) -> String {
+    format!(
+        r#"<form method="post" action="/admin/videos/{}/{}"><button type="submit">{}</button></form>"#,
+        id, action, label
+    )
+}
+
+// Handler for the admin dashboard
+#[get("/admin")]
+pub async fn dashboard(
+    req: HttpRequest,
+    cache: web::Data<Mutex<ThumbnailCache>>,
+    worker: web::Data<ThumbnailWorker>,
+) -> HttpResponse {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+
+    let mut jobs = String::new();
+    for job in JOBS.list().await {
+        jobs.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.1}s</td></tr>",
+            html_escape(&job.id),
+            html_escape(&job.source_url),
+            ago(job.started_at),
+            job.cpu_time.as_secs_f64()
+        ));
+    }
+
+    let mut failures = String::new();
+    for failure in JOBS.failures().await {
+        failures.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td><pre>{}</pre></td><td>{}</td></tr>",
+            html_escape(&failure.id),
+            html_escape(&failure.source_url),
+            ago(failure.failed_at),
+            html_escape(&failure.error),
+            action(&failure.id, "retry", "Retry")
+        ));
+    }
+
+    // Measured in the background, see measure_disk_usage
+    let download_size = STORAGE_BYTES.with_label_values(&["downloads"]).get() as u64;
+    let converted_size = STORAGE_BYTES.with_label_values(&["converted"]).get() as u64;
+
+    let status = worker.status().await;
+    let mut thumbnail_failures = String::new();
+    for failure in &status.failures {
+        thumbnail_failures.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}s</td><td><pre>{}</pre></td></tr>",
+            html_escape(&failure.id),
+            failure.attempts,
+            failure.retry_in.as_secs(),
+            html_escape(&failure.error)
+        ));
+    }
+
+    let mut videos: Vec<_> = cache.lock().await.videos.values().cloned().collect();
+    videos.sort_by_key(|video| std::cmp::Reverse(video.created_at));
+    let mut video_rows = String::new();
+    for video in videos {
+        let thumbnail = if status.pending.contains(&video.id) {
+            "pending"
+        } else if status.failures.iter().any(|failure| failure.id == video.id) {
+            "failed"
+        } else if fs::metadata(thumbnail_path(
+            &video.id,
+            ThumbnailSize::Small,
+            ThumbnailFormat::Jpeg,
+        ))
+        .await
+        .is_ok()
+        {
+            "ok"
+        } else {
+            "missing"
+        };
+
+        video_rows.push_str(&format!(
+            r#"<tr><td><a href="/watch/{id}">{id}</a></td><td>{guild}</td><td>{thumbnail}</td><td>{regenerate}{delete}</td></tr>"#,
+            id = html_escape(&video.id),
+            guild = video
+                .guild_ids
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            thumbnail = thumbnail,
+            regenerate = action(&video.id, "regenerate", "Regenerate"),
+            delete = action(&video.id, "delete", "Delete"),
+        ));
+    }
+
+    let html = format!(
+        r#"
+    <!DOCTYPE html>
+    <html>
+    <head>
+        <title>Admin - YliProxy</title>
+        <style>
+            body {{ font-family: Arial, sans-serif; margin: 0; padding: 20px; background-color: #f5f5f5; }}
+            h1, h2 {{ color: #333; }}
+            table {{ border-collapse: collapse; width: 100%; background-color: white; margin-bottom: 20px; }}
+            th, td {{ text-align: left; padding: 6px 10px; border-bottom: 1px solid #ddd; vertical-align: top; }}
+            pre {{ margin: 0; max-height: 200px; max-width: 800px; overflow: auto; white-space: pre-wrap; font-size: 12px; }}
+            form {{ display: inline; }}
+        </style>
+    </head>
+    <body>
+        <h1><a href="/">YliProxy</a> / Admin</h1>
+
+        <h2>Disk usage</h2>
+        <table>
+            <tr><th>Directory</th><th>Size</th></tr>
+            <tr><td>{download_dir}</td><td>{download_size}</td></tr>
+            <tr><td>{converted_dir}</td><td>{converted_size}</td></tr>
+        </table>
+
+        <h2>Running jobs</h2>
+        <table>
+            <tr><th>ID</th><th>Source</th><th>Started</th><th>CPU time</th></tr>
+            {jobs}
+        </table>
+
+        <h2>Recent failures</h2>
+        <table>
+            <tr><th>ID</th><th>Source</th><th>Failed</th><th>Error</th><th></th></tr>
+            {failures}
+        </table>
+
+        <h2>Thumbnails</h2>
+        <p>{pending} queued</p>
+        <table>
+            <tr><th>ID</th><th>Attempts</th><th>Retry in</th><th>Error</th></tr>
+            {thumbnail_failures}
+        </table>
+
+        <h2>Videos</h2>
+        <table>
+            <tr><th>ID</th><th>Guild</th><th>Thumbnail</th><th></th></tr>
+            {video_rows}
+        </table>
+    </body>
+    </html>
+    "#,
+        download_dir = html_escape(&CONFIG.download_dir),
+        download_size = format_bytes(download_size),
+        converted_dir = html_escape(&CONFIG.converted_dir),
+        converted_size = format_bytes(converted_size),
+        jobs = jobs,
+        failures = failures,
+        pending = status.pending.len(),
+        thumbnail_failures = thumbnail_failures,
+        video_rows = video_rows,
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html)
+}
+
+// Run a failed job again in the background
+#[post("/admin/videos/{id}/retry")]
+pub async fn retry(req: HttpRequest, id: web::Path<String>) -> HttpResponse {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+
+    let Some(in_flight) = shutdown::accept() else {
+        return HttpResponse::ServiceUnavailable().body("Shutting down");
+    };
+    let Some(failure) = JOBS.take_failure(&id).await else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    info!("Retrying failed job {}", failure.id);
+    tokio::spawn(async move {
+        let _in_flight = in_flight;
+        if let Err(e) = run_job(&failure.source_url, &failure.id, failure.origin).await {
+            error!("Retry of {} failed: {:?}", failure.id, e);
+        }
+    });
+
+    back_to_dashboard()
+}
+
+#[post("/admin/videos/{id}/delete")]
+pub async fn delete(
+    req: HttpRequest,
+    id: web::Path<String>,
+    cache: web::Data<Mutex<ThumbnailCache>>,
+) -> HttpResponse {
+    let session = match require_admin(&req).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    if !valid_id(&id) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let Some(metadata) = VideoMetadata::load(&id).await else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if let Err(e) = delete_video(&metadata).await {
+        error!("Failed to delete {}: {:?}", id, e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    cache.lock().await.videos.remove(id.as_str());
+
+    let entry = AuditEntry {
+        user_id: session.user_id,
+        user_name: &session.username,
+        action: "delete_video",
+        guild_id: None,
+        channel_id: None,
+        message_id: None,
+        video_id: Some(&id),
+    };
+    if let Err(e) = audit::record(&entry).await {
+        error!("Failed to write audit entry: {:?}", e);
+    }
+
+    back_to_dashboard()
+}
+
+// Rebuild the thumbnails, renditions and HLS of a video from its stored file, which is left
+// alone so a failure can't lose the video
+#[post("/admin/videos/{id}/regenerate")]
+pub async fn regenerate(
+    req: HttpRequest,
+    id: web::Path<String>,
+    cache: web::Data<Mutex<ThumbnailCache>>,
+    worker: web::Data<ThumbnailWorker>,
+) -> HttpResponse {
+    if let Err(response) = require_admin(&req).await {
+        return response;
+    }
+    if !valid_id(&id) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    if VideoMetadata::load(&id).await.is_none() {
+        return HttpResponse::NotFound().finish();
+    }
+    let Some(in_flight) = shutdown::accept() else {
+        return HttpResponse::ServiceUnavailable().body("Shutting down");
+    };
+
+    let id = id.into_inner();
+    let video = cache.lock().await.videos.get(&id).cloned();
+    let worker = worker.get_ref().clone();
+    info!("Regenerating {}", id);
+    tokio::spawn(async move {
+        let _in_flight = in_flight;
+
+        let key = format!("{}.mp4", id);
+        let primary = Path::new(&CONFIG.converted_dir).join(&key);
+        if fs::metadata(&primary).await.is_err()
+            && let Err(e) = STORAGE.fetch(&key, &primary).await
+        {
+            error!("Failed to fetch {} for regeneration: {:?}", id, e);
+            return;
+        }
+        build_derived(&primary, &id).await;
+
+        // The worker fetches the file again for the thumbnails if it was released
+        if let Err(e) = remove_thumbnails(&id).await {
+            error!("Failed to remove thumbnails of {}: {:?}", id, e);
+        }
+        worker.forget(&id).await;
+        if let Some(video) = video {
+            worker.enqueue(video).await;
+        }
+    });
+
+    back_to_dashboard()
+}
@@ -0,0 +1,283 @@
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::config::CONFIG;
+
+const SESSION_COOKIE: &str = "session";
+const STATE_COOKIE: &str = "oauth_state";
+
+// How long a login attempt may take on Discord's side
+const STATE_TTL: Duration = Duration::from_secs(600);
+
+// A logged in Discord user
+#[derive(Clone)]
+pub struct Session {
+    pub user_id: u64,
+    pub username: String,
+    pub guilds: Vec<u64>,
+    expires_at: SystemTime,
+}
+
+impl Session {
+    pub fn is_member(&self, guild_id: u64) -> bool {
+        self.guilds.contains(&guild_id)
+    }
+}
+
+lazy_static! {
+    // Sessions by cookie token, logins don't survive restarts
+    static ref SESSIONS: Mutex<HashMap<String, Session>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: String,
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct Guild {
+    id: String,
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+}
+
+// Login is only offered when an OAuth2 application is configured
+pub fn login_enabled() -> bool {
+    CONFIG.discord_client_id.is_some() && CONFIG.discord_client_secret.is_some()
+}
+
+// Session of the user making the request, if they're logged in
+pub async fn current_session(req: &HttpRequest) -> Option<Session> {
+    let token = req.cookie(SESSION_COOKIE)?;
+    let mut sessions = SESSIONS.lock().await;
+
+    let session = sessions.get(token.value())?;
+    if session.expires_at < SystemTime::now() {
+        sessions.remove(token.value());
+        return None;
+    }
+
+    Some(session.clone())
+}
+
+fn random_token() -> Result<String, getrandom::Error> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn redirect_uri() -> String {
+    format!("{}/auth/callback", CONFIG.public_url)
+}
+
+fn cookie(name: &str, value: String, max_age: Duration) -> Cookie<'static> {
+    Cookie::build(name.to_string(), value)
+        .path("/")
+        .http_only(true)
+        .secure(CONFIG.public_url.starts_with("https://"))
+        .same_site(SameSite::Lax)
+        .max_age(actix_web::cookie::time::Duration::seconds(
+            max_age.as_secs() as i64,
+        ))
+        .finish()
+}
+
+fn removal_cookie(name: &str) -> Cookie<'static> {
+    let mut cookie = Cookie::build(name.to_string(), "").path("/").finish();
+    cookie.make_removal();
+    cookie
+}
+
+fn redirect(location: &str) -> actix_web::HttpResponseBuilder {
+    let mut response = HttpResponse::Found();
+    response.insert_header((header::LOCATION, location.to_string()));
+    response
+}
+
+// Send the user to Discord to authorize the application
+#[get("/auth/login")]
+pub async fn login() -> HttpResponse {
+    let Some(client_id) = CONFIG
+        .discord_client_id
+        .as_ref()
+        .filter(|_| login_enabled())
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let state = match random_token() {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Failed to generate OAuth state: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let url = match reqwest::Url::parse_with_params(
+        &CONFIG.discord_authorize_url,
+        &[
+            ("response_type", "code"),
+            ("client_id", client_id),
+            ("scope", "identify guilds"),
+            ("redirect_uri", &redirect_uri()),
+            ("state", &state),
+        ],
+    ) {
+        Ok(url) => url,
+        Err(e) => {
+            error!("Invalid DISCORD_AUTHORIZE_URL: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    redirect(url.as_str())
+        .cookie(cookie(STATE_COOKIE, state, STATE_TTL))
+        .finish()
+}
+
+// Finish the login after Discord redirects back with an authorization code
+#[get("/auth/callback")]
+pub async fn callback(req: HttpRequest, query: web::Query<CallbackQuery>) -> HttpResponse {
+    if !login_enabled() {
+        return HttpResponse::NotFound().finish();
+    }
+
+    // The state ties the callback to a login started in this browser
+    let expected_state = req.cookie(STATE_COOKIE);
+    let (Some(code), Some(state), Some(expected_state)) =
+        (&query.code, &query.state, expected_state)
+    else {
+        return HttpResponse::BadRequest().body("Login was cancelled or has expired");
+    };
+    if state != expected_state.value() {
+        return HttpResponse::BadRequest().body("Login was cancelled or has expired");
+    }
+
+    let session = match fetch_session(code).await {
+        Ok(session) => session,
+        Err(e) => {
+            error!("Discord login failed: {:?}", e);
+            return HttpResponse::BadGateway().body("Logging in with Discord failed");
+        }
+    };
+
+    let token = match random_token() {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Failed to generate session token: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    info!("{} ({}) logged in", session.username, session.user_id);
+    {
+        let mut sessions = SESSIONS.lock().await;
+        let now = SystemTime::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(token.clone(), session);
+    }
+
+    redirect("/")
+        .cookie(cookie(SESSION_COOKIE, token, CONFIG.session_ttl))
+        .cookie(removal_cookie(STATE_COOKIE))
+        .finish()
+}
+
+#[get("/auth/logout")]
+pub async fn logout(req: HttpRequest) -> HttpResponse {
+    if let Some(token) = req.cookie(SESSION_COOKIE) {
+        SESSIONS.lock().await.remove(token.value());
+    }
+
+    redirect("/")
+        .cookie(removal_cookie(SESSION_COOKIE))
+        .finish()
+}
+
+// Exchange an authorization code for the user's identity and guilds
+async fn fetch_session(code: &str) -> Result<Session> {
+    let client = reqwest::Client::new();
+    let api_url = CONFIG.discord_api_url.trim_end_matches('/');
+    let redirect_uri = redirect_uri();
+
+    let token: TokenResponse = parse_json(
+        client
+            .post(format!("{}/oauth2/token", api_url))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &redirect_uri),
+                (
+                    "client_id",
+                    CONFIG.discord_client_id.as_deref().unwrap_or_default(),
+                ),
+                (
+                    "client_secret",
+                    CONFIG.discord_client_secret.as_deref().unwrap_or_default(),
+                ),
+            ])
+            .send()
+            .await?,
+    )
+    .await?;
+
+    let user: User = parse_json(
+        client
+            .get(format!("{}/users/@me", api_url))
+            .bearer_auth(&token.access_token)
+            .send()
+            .await?,
+    )
+    .await?;
+
+    let guilds: Vec<Guild> = parse_json(
+        client
+            .get(format!("{}/users/@me/guilds", api_url))
+            .bearer_auth(&token.access_token)
+            .send()
+            .await?,
+    )
+    .await?;
+
+    Ok(Session {
+        user_id: user.id.parse()?,
+        username: user.username,
+        guilds: guilds
+            .iter()
+            .filter_map(|guild| guild.id.parse().ok())
+            .collect(),
+        expires_at: SystemTime::now() + CONFIG.session_ttl,
+    })
+}
+
+async fn parse_json<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(anyhow::anyhow!(
+            "Discord API request failed with {}: {}",
+            status,
+            body
+        ));
+    }
+
+    Ok(serde_json::from_str(&body)?)
+}
@@ -1,13 +1,129 @@
-use actix_web::{get, web, HttpResponse};
+use actix_files::NamedFile;
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use tokio::sync::Mutex;
 use tracing::error;
 
-use crate::web::models::ThumbnailCache;
-use crate::web::thumbnails::{get_video_list, process_missing_thumbnails};
+use crate::audit::{self, AuditEntry};
+use crate::bot::services::yliproxy::YliProxy;
+use crate::config::CONFIG;
+use crate::health::{Check, HEALTH};
+use crate::library::delete_video;
+use crate::metadata::{Posting, VideoMetadata};
+use crate::metrics::render;
+use crate::signing::{normalize_path, sign_url};
+use crate::storage::{is_published_key, STORAGE};
+use crate::web::auth::{current_session, login_enabled, Session};
+use crate::web::models::{ThumbnailCache, VideoInfo};
+use crate::web::previews::SPRITE_FRAMES;
+use crate::web::thumbnails::{get_video_list, thumbs_dir, ThumbnailFormat};
+use crate::web::worker::ThumbnailWorker;
 
-// Handler for the index page
+// Handler for the index page, logged in users see their guilds' videos and
+// everyone else the videos of guilds that aren't hidden
 #[get("/")]
-pub async fn index(cache: web::Data<Mutex<ThumbnailCache>>) -> HttpResponse {
+pub async fn index(
+    req: HttpRequest,
+    cache: web::Data<Mutex<ThumbnailCache>>,
+    worker: web::Data<ThumbnailWorker>,
+) -> HttpResponse {
+    let session = current_session(&req).await;
+
+    match &session {
+        Some(user) => {
+            library(cache, worker, "YliProxy", session.as_ref(), |video| {
+                video
+                    .guild_ids
+                    .iter()
+                    .any(|guild_id| user.is_member(*guild_id))
+                    || video.submitter_ids.contains(&user.user_id)
+            })
+            .await
+        }
+        None => {
+            library(cache, worker, "YliProxy", None, |video| {
+                // Reposts into a hidden guild hide the video everywhere
+                !video
+                    .guild_ids
+                    .iter()
+                    .any(|guild_id| CONFIG.hidden_guilds.contains(guild_id))
+            })
+            .await
+        }
+    }
+}
+
+// Handler for a single guild's library, hidden guilds are members only when login is enabled
+#[get("/g/{guild_id}")]
+pub async fn guild(
+    req: HttpRequest,
+    guild_id: web::Path<u64>,
+    cache: web::Data<Mutex<ThumbnailCache>>,
+    worker: web::Data<ThumbnailWorker>,
+) -> HttpResponse {
+    let guild_id = guild_id.into_inner();
+    let session = current_session(&req).await;
+
+    if login_enabled()
+        && CONFIG.hidden_guilds.contains(&guild_id)
+        && !session
+            .as_ref()
+            .is_some_and(|user| user.is_member(guild_id))
+    {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let title = format!("YliProxy - {}", guild_id);
+    library(cache, worker, &title, session.as_ref(), |video| {
+        video.guild_ids.contains(&guild_id)
+    })
+    .await
+}
+
+// Login state shown in the page header
+fn account_links(session: Option<&Session>) -> String {
+    if !login_enabled() {
+        return String::new();
+    }
+
+    match session {
+        Some(session) => format!(
+            r#"<span class="account">{} &middot; <a href="/auth/logout">Log out</a></span>"#,
+            html_escape(&session.username)
+        ),
+        None => r#"<span class="account"><a href="/auth/login">Log in with Discord</a></span>"#
+            .to_string(),
+    }
+}
+
+// Whether a video ID from a URL is safe to use in file paths
+pub fn valid_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains('/') && !id.contains("..")
+}
+
+pub fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// String literal for an inline script, with `<` escaped so it can't close the script tag
+fn script_string(value: &str) -> String {
+    serde_json::to_string(value)
+        .unwrap_or_default()
+        .replace('<', "\\u003c")
+}
+
+// Render a grid of the videos matching the filter
+async fn library(
+    cache: web::Data<Mutex<ThumbnailCache>>,
+    worker: web::Data<ThumbnailWorker>,
+    title: &str,
+    session: Option<&Session>,
+    filter: impl Fn(&VideoInfo) -> bool,
+) -> HttpResponse {
     let mut cache = cache.lock().await;
 
     // Refresh video list if needed
@@ -21,21 +137,21 @@ pub async fn index(cache: web::Data<Mutex<ThumbnailCache>>) -> HttpResponse {
                 cache.last_refresh = std::time::SystemTime::now();
                 cache.initialized = true;
 
-                // Process thumbnails for the initial load
-                if !cache.initialized {
-                    tokio::spawn(process_missing_thumbnails(cache.videos.clone()));
-                } else {
-                    // Process in the background for subsequent refreshes
-                    tokio::spawn(process_missing_thumbnails(cache.videos.clone()));
-                }
+                // Queue any missing thumbnails, the worker skips duplicates
+                worker.enqueue_missing(&cache.videos).await;
             }
             Err(e) => error!("Failed to refresh video list: {}", e),
         }
     }
 
     // Create a sorted list of videos (newest first)
-    let mut videos: Vec<_> = cache.videos.values().cloned().collect();
-    videos.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    let mut videos: Vec<_> = cache
+        .videos
+        .values()
+        .filter(|video| filter(video))
+        .cloned()
+        .collect();
+    videos.sort_by_key(|video| std::cmp::Reverse(video.created_at));
 
     // Create HTML for the index page
     let mut html = String::from(
@@ -43,43 +159,334 @@ pub async fn index(cache: web::Data<Mutex<ThumbnailCache>>) -> HttpResponse {
     <!DOCTYPE html>
     <html>
     <head>
-        <title>YliProxy</title>
+        <title>__TITLE__</title>
         <style>
             body { font-family: Arial, sans-serif; margin: 0; padding: 20px; background-color: #f5f5f5; }
             .video-grid { display: grid; grid-template-columns: repeat(auto-fill, minmax(300px, 1fr)); gap: 20px; }
             .video-item { background-color: white; border-radius: 8px; overflow: hidden; box-shadow: 0 2px 4px rgba(0,0,0,0.1); }
-            .thumbnail { width: 100%; height: 180px; object-fit: cover; background-color: #ccc; }
+            .media { position: relative; height: 180px; background-color: #ccc; background-repeat: no-repeat; }
+            .thumbnail, .preview { width: 100%; height: 180px; object-fit: cover; }
+            .preview { position: absolute; top: 0; left: 0; display: none; }
+            .video-item.previewing .preview { display: block; }
+            .video-item.scrubbing .thumbnail { visibility: hidden; }
             .video-info { padding: 10px; }
             h1 { color: #333; }
             a { text-decoration: none; color: inherit; }
             .video-title { margin: 5px 0; color: #333; }
+            .account { float: right; font-size: 14px; font-weight: normal; }
+            .account a { text-decoration: underline; }
         </style>
     </head>
     <body>
-        <h1>YliProxy</h1>
+        <h1>__TITLE____ACCOUNT__</h1>
         <div class="video-grid">
     "#,
-    );
+    )
+    .replace("__ACCOUNT__", &account_links(session))
+    .replace("__TITLE__", title);
+
+    // Private videos are only reachable through signed links
+    if CONFIG.signed_private_videos {
+        videos.retain(|video| !video.private);
+    }
 
     for video in videos {
         html.push_str(&format!(r#"
             <div class="video-item">
-                <a href="/{filename}">
-                    <img class="thumbnail" src="/thumbs/{id}.jpg" alt="{filename}" onerror="this.style.backgroundColor='#ccc';">
+                <a href="/watch/{id}">
+                    <div class="media" data-preview="/thumbs/{id}-preview.mp4" data-sprite="/thumbs/{id}-sprite.jpg">
+                        <img class="thumbnail" src="/thumbs/{id}.jpg" alt="{filename}" onerror="this.style.backgroundColor='#ccc';">
+                        <video class="preview" muted loop playsinline preload="none"></video>
+                    </div>
                     <div class="video-info">
                         <h3 class="video-title">{filename}</h3>
                     </div>
                 </a>
             </div>
-        "#, id = video.id, filename = video.filename));
+        "#, id = html_escape(&video.id), filename = html_escape(&video.filename)));
     }
 
     html.push_str(
+        &r#"
+        </div>
+        <script>
+            const SPRITE_FRAMES = __SPRITE_FRAMES__;
+
+            // Cycle through the sprite sheet when the preview clip isn't available
+            function startSprite(item, media) {
+                let frame = 0;
+                media.style.backgroundImage = `url(${media.dataset.sprite})`;
+                media.style.backgroundSize = `${SPRITE_FRAMES * 100}% 100%`;
+                item.classList.add('scrubbing');
+                item.spriteTimer = setInterval(() => {
+                    media.style.backgroundPosition = `${frame * 100 / (SPRITE_FRAMES - 1)}% 0`;
+                    frame = (frame + 1) % SPRITE_FRAMES;
+                }, 300);
+            }
+
+            document.querySelectorAll('.video-item').forEach(item => {
+                const media = item.querySelector('.media');
+                const preview = item.querySelector('.preview');
+
+                preview.addEventListener('error', () => {
+                    item.classList.remove('previewing');
+                    item.previewFailed = true;
+                    if (item.matches(':hover')) startSprite(item, media);
+                });
+
+                item.addEventListener('mouseenter', () => {
+                    if (item.previewFailed) {
+                        startSprite(item, media);
+                        return;
+                    }
+                    if (!preview.src) preview.src = media.dataset.preview;
+                    item.classList.add('previewing');
+                    preview.play().catch(() => {});
+                });
+
+                item.addEventListener('mouseleave', () => {
+                    item.classList.remove('previewing', 'scrubbing');
+                    clearInterval(item.spriteTimer);
+                    media.style.backgroundImage = '';
+                    preview.pause();
+                });
+            });
+        </script>
+    </body>
+    </html>
+    "#
+        .replace("__SPRITE_FRAMES__", &SPRITE_FRAMES.to_string()),
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html)
+}
+
+// Handler for the watch page with seek bar previews
+#[get("/watch/{id}")]
+pub async fn watch(req: HttpRequest, id: web::Path<String>) -> HttpResponse {
+    let id = id.into_inner();
+    if !valid_id(&id) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    // Remote backends may hold videos that aren't cached locally
+    let video_path = std::path::Path::new(&CONFIG.converted_dir).join(format!("{}.mp4", id));
+    if CONFIG.storage_backend == "local" && tokio::fs::metadata(&video_path).await.is_err() {
+        return HttpResponse::NotFound().finish();
+    }
+    let video_url = media_url(&format!("{}.mp4", id)).await;
+
+    let metadata = VideoMetadata::load(&id).await;
+
+    // Prefer the adaptive stream when the video has been packaged as HLS, public buckets
+    // serve it directly and otherwise it's kept locally
+    let master_key = format!("{}/hls/master.m3u8", id);
+    let master_path = std::path::Path::new(&CONFIG.converted_dir).join(&master_key);
+    let hls_url = if tokio::fs::metadata(&master_path).await.is_ok() {
+        let path = format!("/{}", master_key);
+        sign_url(&path, &path)
+    } else if CONFIG.s3_public_url.is_some()
+        && metadata.as_ref().is_some_and(|metadata| metadata.hls)
+    {
+        media_url(&master_key).await
+    } else {
+        String::new()
+    };
+
+    // Quality selector options, only shown when there's something to choose from
+    let mut qualities = String::new();
+    if !hls_url.is_empty() {
+        qualities.push_str(&format!(
+            r#"<option value="{}">Auto</option>"#,
+            html_escape(&hls_url)
+        ));
+    }
+    if let Some(metadata) = &metadata {
+        let audio = metadata
+            .renditions
+            .iter()
+            .filter(|rendition| rendition.audio_only);
+        for rendition in metadata.video_renditions().into_iter().chain(audio) {
+            qualities.push_str(&format!(
+                r#"<option value="{}">{}</option>"#,
+                html_escape(&media_url(&rendition.file_name).await),
+                html_escape(&rendition.name)
+            ));
+        }
+    }
+    // Submitters can remove their own videos
+    let session = current_session(&req).await;
+    let owner = match (&session, &metadata) {
+        (Some(session), Some(metadata)) => metadata.submitter_ids().contains(&session.user_id),
+        _ => false,
+    };
+    let delete_form = if owner {
+        format!(
+            r#"<form class="delete" method="post" action="/watch/{}/delete" onsubmit="return confirm('Delete this video?');"><button type="submit">Delete</button></form>"#,
+            html_escape(&id)
+        )
+    } else {
+        String::new()
+    };
+
+    // hls.js is served from our own origin so a third party can't change what runs here
+    let hls_script = if tokio::fs::metadata(&CONFIG.hls_js_file).await.is_ok() {
+        r#"<script src="/static/hls.min.js"></script>"#
+    } else {
+        ""
+    };
+
+    let quality_display = if qualities.matches("<option").count() > 1 {
+        "inline-block"
+    } else {
+        "none"
+    };
+
+    let html = format!(
         r#"
+    <!DOCTYPE html>
+    <html>
+    <head>
+        <title>{id} - YliProxy</title>
+        <style>
+            body {{ font-family: Arial, sans-serif; margin: 0; padding: 20px; background-color: #f5f5f5; }}
+            .player {{ max-width: 1280px; margin: 0 auto; }}
+            video {{ width: 100%; background-color: #000; border-radius: 8px; }}
+            .scrubber {{ position: relative; height: 12px; margin-top: 8px; background-color: #ccc; border-radius: 6px; cursor: pointer; }}
+            .progress {{ height: 100%; width: 0; background-color: #333; border-radius: 6px; }}
+            .seek-preview {{ position: absolute; bottom: 20px; display: none; border: 2px solid #333; background-repeat: no-repeat; transform: translateX(-50%); }}
+            .seek-time {{ position: absolute; bottom: 0; width: 100%; text-align: center; color: white; background-color: rgba(0,0,0,0.6); font-size: 12px; }}
+            h1 {{ color: #333; font-size: 20px; }}
+            a {{ color: #333; }}
+            .quality {{ display: {quality_display}; float: right; }}
+            .delete {{ margin-top: 12px; }}
+        </style>
+    </head>
+    <body>
+        <div class="player">
+            <h1><a href="/">YliProxy</a> / {id}<select class="quality" id="quality">{qualities}</select></h1>
+            <video id="video" controls preload="metadata" src="{video_url}" poster="/thumbs/{id}-poster.jpg">
+                <track kind="metadata" label="thumbnails" src="/thumbs/{id}-seek.vtt" default>
+            </video>
+            <div class="scrubber" id="scrubber">
+                <div class="progress" id="progress"></div>
+                <div class="seek-preview" id="seek-preview"><div class="seek-time" id="seek-time"></div></div>
+            </div>
+            {delete_form}
         </div>
+        {hls_script}
+        <script>
+            const video = document.getElementById('video');
+            const hlsUrl = {hls_url};
+
+            let hls = null;
+
+            // Safari plays HLS natively, other browsers go through hls.js. Signed playlists
+            // always do, as only hls.js passes their signature on to the files they list
+            function loadSource(url) {{
+                if (hls) {{
+                    hls.destroy();
+                    hls = null;
+                }}
+                const parsed = new URL(url, location.href);
+                const signed = parsed.searchParams.has('signature');
+                if (parsed.pathname.endsWith('.m3u8') && (signed || !video.canPlayType('application/vnd.apple.mpegurl'))) {{
+                    if (window.Hls && Hls.isSupported()) {{
+                        const config = signed ? {{
+                            xhrSetup: (xhr, resource) => {{
+                                if (!resource.includes('signature=')) {{
+                                    xhr.open('GET', resource + (resource.includes('?') ? '&' : '?') + parsed.search.slice(1), true);
+                                }}
+                            }}
+                        }} : {{}};
+                        hls = new Hls(config);
+                        hls.loadSource(url);
+                        hls.attachMedia(video);
+                    }}
+                    return;
+                }}
+                video.src = url;
+            }}
+
+            if (hlsUrl) loadSource(hlsUrl);
+
+            // Switch quality without losing the playback position
+            document.getElementById('quality').addEventListener('change', event => {{
+                const time = video.currentTime;
+                const paused = video.paused;
+                loadSource(event.target.value);
+                video.addEventListener('loadedmetadata', () => {{
+                    video.currentTime = time;
+                    if (!paused) video.play();
+                }}, {{ once: true }});
+            }});
+
+            const scrubber = document.getElementById('scrubber');
+            const progress = document.getElementById('progress');
+            const preview = document.getElementById('seek-preview');
+            const seekTime = document.getElementById('seek-time');
+            const track = video.textTracks[0];
+            track.mode = 'hidden';
+
+            function timeAt(event) {{
+                const rect = scrubber.getBoundingClientRect();
+                const ratio = Math.min(Math.max((event.clientX - rect.left) / rect.width, 0), 1);
+                return {{ time: ratio * (video.duration || 0), x: event.clientX - rect.left }};
+            }}
+
+            function formatTime(seconds) {{
+                const m = Math.floor(seconds / 60);
+                const s = Math.floor(seconds % 60).toString().padStart(2, '0');
+                return `${{m}}:${{s}}`;
+            }}
+
+            // Find the sprite region for a point in time from the thumbnails track
+            function cueAt(time) {{
+                if (!track.cues) return null;
+                for (const cue of track.cues) {{
+                    if (time >= cue.startTime && time < cue.endTime) return cue;
+                }}
+                return null;
+            }}
+
+            scrubber.addEventListener('mousemove', event => {{
+                const {{ time, x }} = timeAt(event);
+                const cue = cueAt(time);
+                if (!cue) {{
+                    preview.style.display = 'none';
+                    return;
+                }}
+                const [file, fragment] = cue.text.trim().split('#xywh=');
+                const [fx, fy, fw, fh] = fragment.split(',').map(Number);
+                preview.style.backgroundImage = `url(/thumbs/${{file}})`;
+                preview.style.backgroundPosition = `-${{fx}}px -${{fy}}px`;
+                preview.style.width = `${{fw}}px`;
+                preview.style.height = `${{fh}}px`;
+                preview.style.left = `${{x}}px`;
+                preview.style.display = 'block';
+                seekTime.textContent = formatTime(time);
+            }});
+
+            scrubber.addEventListener('mouseleave', () => preview.style.display = 'none');
+            scrubber.addEventListener('click', event => {{
+                video.currentTime = timeAt(event).time;
+            }});
+            video.addEventListener('timeupdate', () => {{
+                progress.style.width = `${{100 * video.currentTime / (video.duration || 1)}}%`;
+            }});
+        </script>
     </body>
     </html>
     "#,
+        id = html_escape(&id),
+        hls_script = hls_script,
+        hls_url = script_string(&hls_url),
+        video_url = html_escape(&video_url),
+        delete_form = delete_form,
+        qualities = qualities,
+        quality_display = quality_display
     );
 
     HttpResponse::Ok()
@@ -87,8 +494,259 @@ pub async fn index(cache: web::Data<Mutex<ThumbnailCache>>) -> HttpResponse {
         .body(html)
 }
 
+// Handler for deleting a video, only those who posted it may do so. Videos also posted
+// by others are kept and only the user's own postings are dropped
+#[post("/watch/{id}/delete")]
+pub async fn delete(
+    req: HttpRequest,
+    id: web::Path<String>,
+    cache: web::Data<Mutex<ThumbnailCache>>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    if !valid_id(&id) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let Some(session) = current_session(&req).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+    let Some(metadata) = VideoMetadata::load(&id).await else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let is_admin = CONFIG.admin_user_ids.contains(&session.user_id);
+    let own = |posting: &Posting| posting.submitter_id == Some(session.user_id);
+    let postings = metadata.all_postings();
+    if !is_admin && !postings.iter().any(own) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    // Reposts recorded before postings were tracked only left an alias
+    let shared = postings.iter().any(|posting| !own(posting))
+        || (metadata.postings.is_empty() && !metadata.aliases.is_empty());
+    let action = if is_admin || !shared {
+        if let Err(e) = delete_video(&metadata).await {
+            error!("Failed to delete {}: {:?}", id, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+        cache.lock().await.videos.remove(&id);
+        "delete_video"
+    } else if metadata.postings.is_empty() {
+        return HttpResponse::Conflict()
+            .body("The video was also posted elsewhere, ask an administrator to delete it.");
+    } else {
+        let metadata =
+            match VideoMetadata::update(&id, |metadata| metadata.remove_postings(own)).await {
+                Ok(Some(metadata)) => metadata,
+                Ok(None) => return HttpResponse::NotFound().finish(),
+                Err(e) => {
+                    error!("Failed to remove postings of {}: {:?}", id, e);
+                    return HttpResponse::InternalServerError().finish();
+                }
+            };
+        if let Some(video) = cache.lock().await.videos.get_mut(&id) {
+            video.guild_ids = metadata.guild_ids();
+            video.submitter_ids = metadata.submitter_ids();
+        }
+        "detach_video"
+    };
+
+    let entry = AuditEntry {
+        user_id: session.user_id,
+        user_name: &session.username,
+        action,
+        guild_id: None,
+        channel_id: None,
+        message_id: None,
+        video_id: Some(&id),
+    };
+    if let Err(e) = audit::record(&entry).await {
+        error!("Failed to write audit entry: {:?}", e);
+    }
+
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/"))
+        .finish()
+}
+
+// Liveness probe, fails while a component is down or being restarted
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    health_response(HEALTH.liveness())
+}
+
+// Readiness probe covering the gateway, ffmpeg, data directories and queues
+#[get("/readyz")]
+pub async fn readyz(worker: web::Data<ThumbnailWorker>) -> HttpResponse {
+    let mut checks = HEALTH.readiness().await;
+    let thumbnails = if worker.is_running() {
+        Ok(())
+    } else {
+        Err("Thumbnail worker has stopped".to_string())
+    };
+    checks.push(Check::new("thumbnails", thumbnails));
+
+    health_response(checks)
+}
+
+fn health_response(checks: Vec<Check>) -> HttpResponse {
+    let healthy = checks.iter().all(|check| check.ok);
+    let body = serde_json::json!({
+        "status": if healthy { "ok" } else { "unavailable" },
+        "checks": checks,
+    });
+
+    if healthy {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+// Compare secrets without leaking how much of them matched through timing
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// Handler for Prometheus scrapes, only enabled with a bearer token
+#[get("/metrics")]
+pub async fn metrics(req: HttpRequest) -> HttpResponse {
+    let Some(token) = &CONFIG.metrics_token else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| tokens_match(given.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render())
+}
+
+// Resolve a media URL through the storage backend, falling back to this server
+async fn media_url(key: &str) -> String {
+    match YliProxy::get_file_url(key).await {
+        Ok(url) => url,
+        Err(e) => {
+            error!("Failed to resolve URL for {}: {:?}", key, e);
+            format!("/{}", key)
+        }
+    }
+}
+
+// Redirect requests for media missing locally to the storage backend, only published
+// files are handed out
+pub async fn storage_redirect(req: HttpRequest) -> HttpResponse {
+    let Some(path) = normalize_path(req.path()) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let key = path.trim_start_matches('/');
+    if CONFIG.storage_backend == "local" || !is_published_key(key) {
+        return HttpResponse::NotFound().finish();
+    }
+
+    match STORAGE.url(key).await {
+        Ok(url) => HttpResponse::Found()
+            .insert_header((header::LOCATION, url))
+            .finish(),
+        Err(e) => {
+            error!("Failed to resolve URL for {}: {:?}", key, e);
+            HttpResponse::NotFound().finish()
+        }
+    }
+}
+
+// Quality value the Accept header gives a media type. Wildcards don't count, clients that
+// can't decode the newer image formats send them too
+fn accept_quality(accept: &str, mime: &str) -> Option<f32> {
+    accept.split(',').find_map(|range| {
+        let mut parts = range.split(';');
+        if !parts.next()?.trim().eq_ignore_ascii_case(mime) {
+            return None;
+        }
+
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=')
+                && name.trim().eq_ignore_ascii_case("q")
+            {
+                return value.trim().parse().ok();
+            }
+        }
+        Some(1.0)
+    })
+}
+
+// Handler for the hls.js copy used by the watch page
+#[get("/static/hls.min.js")]
+pub async fn hls_js(req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    let file = NamedFile::open_async(&CONFIG.hls_js_file)
+        .await?
+        .use_last_modified(true);
+    Ok(file.into_response(&req))
+}
+
+// Handler for thumbnails, serves the best format the client accepts
+#[get("/thumbs/{name}")]
+pub async fn thumbnail(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let name = name.into_inner();
+    if name.contains('/') || name.contains("..") {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    // Only JPEG requests are negotiated, explicit formats are served as-is
+    let mut path = thumbs_dir().join(&name);
+    if let Some(stem) = name.strip_suffix(".jpg") {
+        let accept = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        // Accepted formats by the client's preference, JPEG is the fallback for everyone
+        let mut formats: Vec<_> = ThumbnailFormat::ALL
+            .into_iter()
+            .filter(|format| *format != ThumbnailFormat::Jpeg)
+            .filter_map(|format| Some((format, accept_quality(accept, format.mime())?)))
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        formats.sort_by(|a, b| b.1.total_cmp(&a.1));
+        formats.push((ThumbnailFormat::Jpeg, 1.0));
+
+        for (format, _) in formats {
+            let candidate = thumbs_dir().join(format!("{}.{}", stem, format.extension()));
+            if tokio::fs::metadata(&candidate).await.is_ok() {
+                path = candidate;
+                break;
+            }
+        }
+    }
+
+    let file = NamedFile::open_async(&path).await?.use_last_modified(true);
+    let mut response = file.into_response(&req);
+    response
+        .headers_mut()
+        .insert(header::VARY, header::HeaderValue::from_static("Accept"));
+
+    Ok(response)
+}
+
 // Initialize the cache with data on server startup
-pub async fn initialize_cache(cache: web::Data<Mutex<ThumbnailCache>>) {
+pub async fn initialize_cache(
+    cache: web::Data<Mutex<ThumbnailCache>>,
+    worker: web::Data<ThumbnailWorker>,
+) {
     tracing::info!("Initializing video cache on startup");
 
     let mut cache_lock = cache.lock().await;
@@ -101,11 +759,8 @@ pub async fn initialize_cache(cache: web::Data<Mutex<ThumbnailCache>>) {
             cache_lock.last_refresh = std::time::SystemTime::now();
             cache_lock.initialized = true;
 
-            // Process thumbnails in the background
-            let videos_clone = cache_lock.videos.clone();
-            tokio::spawn(async move {
-                process_missing_thumbnails(videos_clone).await;
-            });
+            // Queue thumbnails for the background worker
+            worker.enqueue_missing(&cache_lock.videos).await;
 
             tracing::info!("Cache initialized with {} videos", cache_lock.videos.len());
         }
@@ -0,0 +1,191 @@
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage, HttpResponse};
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::time::Instant;
+use tracing::{debug, field, info, info_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::CONFIG;
+use crate::metadata::VideoMetadata;
+use crate::metrics::{HTTP_REQUESTS, HTTP_REQUEST_DURATION, HTTP_RESPONSE_BYTES};
+use crate::ratelimit::IP_LIMITER;
+use crate::signing::{has_signature, is_video_file, normalize_path, verify, video_id};
+use crate::telemetry::{self, parse_traceparent, span_context};
+use crate::web::auth::current_session;
+
+// Metadata of the video a request is for, kept in the request extensions so the
+// middlewares load it only once
+#[derive(Clone)]
+struct RequestVideo(Option<Rc<VideoMetadata>>);
+
+async fn request_video(req: &ServiceRequest) -> Option<Rc<VideoMetadata>> {
+    if let Some(video) = req.extensions().get::<RequestVideo>() {
+        return video.0.clone();
+    }
+
+    let metadata = match normalize_path(req.path()).as_deref().and_then(video_id) {
+        Some(id) => VideoMetadata::load(id).await.map(Rc::new),
+        None => None,
+    };
+    req.extensions_mut().insert(RequestVideo(metadata.clone()));
+    metadata
+}
+
+// Reject bad signatures and unsigned requests for video files, and for anything of a private
+// video when those require one
+pub async fn require_signature(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if CONFIG.url_signing_secret.is_none() {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    // Check the path the file server will actually serve, not the encoded one
+    let Some(path) = normalize_path(req.path()) else {
+        info!("Refused request with a malformed path: {}", req.path());
+        return Ok(req.into_response(HttpResponse::BadRequest().finish()));
+    };
+    let query = req.query_string().to_string();
+
+    let allowed = if has_signature(&query) {
+        verify(&path, &query)
+    } else if is_video_file(&path) {
+        false
+    } else if CONFIG.signed_private_videos {
+        !request_video(&req)
+            .await
+            .is_some_and(|metadata| metadata.private)
+    } else {
+        true
+    };
+
+    if !allowed {
+        info!("Refused request without a valid signature: {}", path);
+        return Ok(req.into_response(HttpResponse::Forbidden().finish()));
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+// Trace requests, continuing the caller's trace and linking video fetches to their conversion
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let span = info_span!(
+        "http_request",
+        method = %req.method(),
+        path = req.path(),
+        status = field::Empty
+    );
+
+    if telemetry::enabled() {
+        if let Some(traceparent) = req
+            .headers()
+            .get("traceparent")
+            .and_then(|value| value.to_str().ok())
+            && let Err(e) = span.set_parent(parse_traceparent(traceparent))
+        {
+            debug!("Ignoring traceparent {}: {:?}", traceparent, e);
+        }
+
+        if let Some(metadata) = request_video(&req).await
+            && let Some(traceparent) = &metadata.traceparent
+        {
+            span.add_link(span_context(traceparent));
+        }
+    }
+
+    let response = next.call(req).instrument(span.clone()).await?;
+    span.record("status", response.status().as_u16());
+
+    Ok(response)
+}
+
+// Limit requests per client IP, health checks and admins are exempt
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let exempt = matches!(req.path(), "/healthz" | "/readyz");
+
+    if !exempt
+        && let Some(ip) = client_ip(&req)
+        && let Err(retry_after) = IP_LIMITER.acquire(&ip).await
+        && !current_session(req.request())
+            .await
+            .is_some_and(|session| CONFIG.admin_user_ids.contains(&session.user_id))
+    {
+        info!("Rate limited requests from {}", ip);
+        let response = HttpResponse::TooManyRequests()
+            .insert_header((
+                header::RETRY_AFTER,
+                retry_after.as_secs().max(1).to_string(),
+            ))
+            .finish();
+        return Ok(req.into_response(response));
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+// Address of the client, from X-Forwarded-For when running behind trusted proxies
+fn client_ip(req: &ServiceRequest) -> Option<IpAddr> {
+    if CONFIG.trust_forwarded_for {
+        // Clients can put anything on the left, only the entries our proxies appended count
+        let entries: Vec<_> = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        if let Some(entry) = entries
+            .len()
+            .checked_sub(CONFIG.forwarded_hops)
+            .map(|index| entries[index])
+        {
+            return entry
+                .parse()
+                .ok()
+                .or_else(|| entry.parse::<SocketAddr>().ok().map(|addr| addr.ip()));
+        }
+    }
+
+    req.peer_addr().map(|addr| addr.ip())
+}
+
+// Count requests, response sizes and latency per route
+pub async fn record_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let response = next.call(req).await?;
+
+    // Route patterns keep the label set small, files are all counted together
+    let route = response
+        .request()
+        .match_pattern()
+        .filter(|pattern| !pattern.is_empty())
+        .unwrap_or_else(|| "files".to_string());
+
+    HTTP_REQUESTS
+        .with_label_values(&[route.as_str(), response.status().as_str()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[route.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    if let BodySize::Sized(size) = response.response().body().size() {
+        HTTP_RESPONSE_BYTES
+            .with_label_values(&[route.as_str()])
+            .inc_by(size);
+    }
+
+    Ok(response)
+}
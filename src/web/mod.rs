@@ -1,5 +1,10 @@
 pub mod server;
 
+mod admin;
+mod auth;
 mod handlers;
+mod middleware;
 mod models;
+mod previews;
 mod thumbnails;
+mod worker;
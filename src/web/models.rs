@@ -8,6 +8,13 @@ pub struct VideoInfo {
     pub filename: String,
     pub thumbnail: String,
     pub created_at: SystemTime,
+    pub private: bool,
+    // Every guild and user that posted the video
+    pub guild_ids: Vec<u64>,
+    pub submitter_ids: Vec<u64>,
+    // Trace of the conversion, linked from the thumbnail work
+    #[serde(skip)]
+    pub traceparent: Option<String>,
 }
 
 // Cache for thumbnails to avoid checking the filesystem too often
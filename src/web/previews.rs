@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::bot::services::probe::probe;
+use crate::ffmpeg::{run_checked, write_atomically};
+use crate::web::thumbnails::thumbs_dir;
+
+// Length of the looping hover preview in seconds
+const PREVIEW_LENGTH: f64 = 3.0;
+
+// Relative position in the video where the hover preview starts
+const PREVIEW_POSITION: f64 = 0.1;
+
+// Width of the hover preview and of each frame in the sprite sheet
+const PREVIEW_WIDTH: u32 = 320;
+const SPRITE_FRAME_WIDTH: u32 = 160;
+
+// Number of frames in the hover sprite sheet, laid out in a single row
+pub const SPRITE_FRAMES: u32 = 10;
+
+// Videos shorter than this don't get seek previews
+const SEEK_MIN_DURATION: f64 = 30.0;
+
+// Seconds between seek preview frames, stretched so a sheet stays below the frame limit
+const SEEK_INTERVAL: f64 = 5.0;
+const SEEK_MAX_FRAMES: u32 = 100;
+
+// Seek sprite sheet layout
+const SEEK_FRAME_WIDTH: u32 = 160;
+const SEEK_COLUMNS: u32 = 10;
+
+// Path of the muted looping preview clip for a video
+pub fn preview_path(id: &str) -> PathBuf {
+    thumbs_dir().join(format!("{}-preview.mp4", id))
+}
+
+// Path of the hover sprite sheet for a video
+pub fn sprite_path(id: &str) -> PathBuf {
+    thumbs_dir().join(format!("{}-sprite.jpg", id))
+}
+
+// Path of the seek preview sprite sheet for a video
+pub fn seek_sprite_path(id: &str) -> PathBuf {
+    thumbs_dir().join(format!("{}-seek.jpg", id))
+}
+
+// Path of the WebVTT thumbnails track for a video
+pub fn seek_track_path(id: &str) -> PathBuf {
+    thumbs_dir().join(format!("{}-seek.vtt", id))
+}
+
+// Generate the hover preview clip and sprite sheet, skipping existing ones
+pub async fn generate_previews(video_path: &Path, id: &str) -> Result<(), std::io::Error> {
+    let duration = probe(video_path).await.ok().and_then(|info| info.duration);
+
+    let preview = preview_path(id);
+    if fs::metadata(&preview).await.is_err() {
+        write_atomically(&preview, |temp_path| async move {
+            generate_preview_clip(video_path, &temp_path, duration).await
+        })
+        .await?;
+    }
+
+    let sprite = sprite_path(id);
+    if fs::metadata(&sprite).await.is_err() {
+        write_atomically(&sprite, |temp_path| async move {
+            generate_sprite_sheet(video_path, &temp_path, duration).await
+        })
+        .await?;
+    }
+
+    let track = seek_track_path(id);
+    if fs::metadata(&track).await.is_err() {
+        generate_seek_previews(video_path, id, duration).await?;
+    }
+
+    Ok(())
+}
+
+async fn generate_preview_clip(
+    video_path: &Path,
+    preview_path: &Path,
+    duration: Option<f64>,
+) -> Result<(), std::io::Error> {
+    // Short clips are used whole, longer ones are sampled a bit into the video
+    let offset = duration
+        .filter(|duration| *duration > PREVIEW_LENGTH * 2.0)
+        .map(|duration| format!("{:.3}", duration * PREVIEW_POSITION));
+    let length = PREVIEW_LENGTH.to_string();
+    let filter = format!("scale='min({},iw)':-2", PREVIEW_WIDTH);
+
+    let mut args = vec!["-y"];
+    if let Some(offset) = &offset {
+        args.extend(["-ss", offset]);
+    }
+
+    args.extend([
+        "-i",
+        video_path.to_str().unwrap(),
+        "-t",
+        &length,
+        "-vf",
+        &filter,
+        "-an",
+        "-c:v",
+        "libx264",
+        "-preset",
+        "veryfast",
+        "-crf",
+        "30",
+        "-pix_fmt",
+        "yuv420p",
+        "-movflags",
+        "+faststart",
+        preview_path.to_str().unwrap(),
+    ]);
+
+    run_checked(&args, None, "generate preview").await
+}
+
+async fn generate_sprite_sheet(
+    video_path: &Path,
+    sprite_path: &Path,
+    duration: Option<f64>,
+) -> Result<(), std::io::Error> {
+    // Spread the frames evenly over the video, or one per second if unknown
+    let fps = match duration {
+        Some(duration) if duration > 0.0 => format!("{}/{:.3}", SPRITE_FRAMES, duration),
+        _ => "1".to_string(),
+    };
+    let filter = format!(
+        "fps={},scale={}:-2,tile={}x1",
+        fps, SPRITE_FRAME_WIDTH, SPRITE_FRAMES
+    );
+
+    let args = [
+        "-y",
+        "-i",
+        video_path.to_str().unwrap(),
+        "-vf",
+        &filter,
+        "-frames:v",
+        "1",
+        "-q:v",
+        "4",
+        sprite_path.to_str().unwrap(),
+    ];
+
+    run_checked(&args, None, "generate preview").await
+}
+
+// Generate the seek sprite sheet and its WebVTT track
+//
+// Short videos get a track without cues so they aren't processed again.
+async fn generate_seek_previews(
+    video_path: &Path,
+    id: &str,
+    duration: Option<f64>,
+) -> Result<(), std::io::Error> {
+    let mut track = String::from("WEBVTT\n");
+
+    if let Some(duration) = duration
+        && duration >= SEEK_MIN_DURATION
+    {
+        let interval = SEEK_INTERVAL.max(duration / SEEK_MAX_FRAMES as f64);
+        let frames = (duration / interval).ceil() as u32;
+        let rows = frames.div_ceil(SEEK_COLUMNS);
+
+        let sprite = seek_sprite_path(id);
+        let filter = format!(
+            "fps=1/{:.3},scale={}:-2,tile={}x{}",
+            interval, SEEK_FRAME_WIDTH, SEEK_COLUMNS, rows
+        );
+        write_atomically(&sprite, |temp_path| async move {
+            let args = [
+                "-y",
+                "-i",
+                video_path.to_str().unwrap(),
+                "-vf",
+                &filter,
+                "-frames:v",
+                "1",
+                "-q:v",
+                "5",
+                temp_path.to_str().unwrap(),
+            ];
+            run_checked(&args, None, "generate preview").await
+        })
+        .await?;
+
+        // The frame height depends on the aspect ratio, so derive it from the sheet
+        let (sheet_width, sheet_height) = probe(&sprite)
+            .await
+            .ok()
+            .and_then(|info| info.width.zip(info.height))
+            .ok_or_else(|| std::io::Error::other("Failed to read seek sprite dimensions"))?;
+        let frame_width = sheet_width / SEEK_COLUMNS;
+        let frame_height = sheet_height / rows;
+        let sprite_name = format!("{}-seek.jpg", id);
+
+        for frame in 0..frames {
+            let start = frame as f64 * interval;
+            let end = (start + interval).min(duration);
+            track.push_str(&format!(
+                "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+                vtt_timestamp(start),
+                vtt_timestamp(end),
+                sprite_name,
+                (frame % SEEK_COLUMNS) * frame_width,
+                (frame / SEEK_COLUMNS) * frame_height,
+                frame_width,
+                frame_height
+            ));
+        }
+    }
+
+    write_atomically(&seek_track_path(id), |temp_path| {
+        fs::write(temp_path, track)
+    })
+    .await
+}
+
+// Format seconds as a WebVTT timestamp
+fn vtt_timestamp(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
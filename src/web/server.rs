@@ -1,4 +1,5 @@
 use actix_files::Files;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -7,9 +8,18 @@ use tokio::sync::{Mutex, Notify};
 use tracing::info;
 
 use crate::config::CONFIG;
-use crate::web::handlers::{index, initialize_cache};
+use crate::library::measure_disk_usage;
+use crate::web::admin;
+use crate::web::admin::{dashboard, regenerate, retry};
+use crate::web::auth::{callback, login, logout};
+use crate::web::handlers::{
+    delete, guild, healthz, hls_js, index, initialize_cache, metrics, readyz, storage_redirect,
+    thumbnail, watch,
+};
+use crate::web::middleware::{rate_limit, record_metrics, require_signature, trace_request};
 use crate::web::models::ThumbnailCache;
 use crate::web::thumbnails::ensure_thumbs_dir;
+use crate::web::worker::ThumbnailWorker;
 
 pub async fn run_file_server(shutdown_signal: Arc<Notify>) -> std::io::Result<()> {
     let addr = format!("{}:{}", CONFIG.host, CONFIG.port)
@@ -17,40 +27,70 @@ pub async fn run_file_server(shutdown_signal: Arc<Notify>) -> std::io::Result<()
         .expect("Failed to parse host and port into SocketAddr");
 
     // Create thumbs directory if it doesn't exist
-    let thumbs_dir = ensure_thumbs_dir()?;
+    ensure_thumbs_dir()?;
 
     // Create the thumbnail cache
     let thumbnail_cache = web::Data::new(Mutex::new(ThumbnailCache::new()));
 
+    // Start the thumbnail worker
+    let thumbnail_worker = web::Data::new(ThumbnailWorker::start());
+
+    tokio::spawn(measure_disk_usage());
+
     // Initialize the cache on startup
     let cache_clone = thumbnail_cache.clone();
+    let worker_clone = thumbnail_worker.clone();
     tokio::spawn(async move {
-        initialize_cache(cache_clone).await;
+        initialize_cache(cache_clone, worker_clone).await;
     });
 
     let server = HttpServer::new(move || {
         let converted_path = PathBuf::from(&CONFIG.converted_dir);
         App::new()
+            .wrap(from_fn(require_signature))
+            .wrap(from_fn(rate_limit))
+            .wrap(from_fn(record_metrics))
+            .wrap(from_fn(trace_request))
             .app_data(thumbnail_cache.clone())
+            .app_data(thumbnail_worker.clone())
             .service(index)
-            .service(Files::new("/thumbs", thumbs_dir.clone()))
+            .service(metrics)
+            .service(healthz)
+            .service(readyz)
+            .service(guild)
+            .service(watch)
+            .service(delete)
+            .service(login)
+            .service(callback)
+            .service(logout)
+            .service(dashboard)
+            .service(retry)
+            .service(admin::delete)
+            .service(regenerate)
+            .service(thumbnail)
+            .service(hls_js)
             .service(
                 Files::new("/", converted_path)
                     .index_file("") // No index file, we handle it with our custom handler
-                    .use_last_modified(true),
+                    .use_last_modified(true)
+                    .default_handler(web::to(storage_redirect)),
             )
     })
+    // Shutdown is coordinated by main so jobs can drain first
+    .disable_signals()
     .bind(addr)?;
 
     info!("Starting file server on: {addr}");
 
-    let server_handle = server.run();
+    let server = server.run();
+    let server_handle = server.handle();
+
+    // Let in-flight requests finish instead of dropping their connections
+    tokio::spawn(async move {
+        shutdown_signal.notified().await;
+        info!("Shutdown signal received, stopping web server");
+        server_handle.stop(true).await;
+    });
 
-    tokio::select! {
-        result = server_handle => result,
-        _ = shutdown_signal.notified() => {
-            info!("Shutdown signal received, stopping web server");
-            Ok(())
-        }
-    }
+    server.await
 }
@@ -1,77 +1,236 @@
-use async_process::Command;
-use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use tokio::fs;
-use tracing::{error, info};
+use tracing::{error, warn};
 
+use crate::bot::services::probe::probe;
 use crate::config::CONFIG;
+use crate::ffmpeg::{run_checked, write_atomically};
+use crate::metadata::VideoMetadata;
+use crate::storage::STORAGE;
 use crate::web::models::VideoInfo;
 
-// Process missing thumbnails in the background
-pub async fn process_missing_thumbnails(videos: HashMap<String, VideoInfo>) {
-    let thumbs_dir = Path::new(&CONFIG.converted_dir).join("thumbs");
+// Relative position in the video where the thumbnail frame is picked
+const FRAME_POSITION: f64 = 0.1;
 
-    // Create thumbs directory if it doesn't exist
-    if !thumbs_dir.exists() {
-        if let Err(e) = fs::create_dir_all(&thumbs_dir).await {
-            error!("Failed to create thumbs directory: {}", e);
-            return;
+// Videos shorter than this are not seeked into at all
+const MIN_SEEKABLE_DURATION: f64 = 2.0;
+
+// Number of frames the thumbnail filter compares to skip black or blurry frames
+const SCENE_SAMPLE_FRAMES: u32 = 50;
+
+// Generated thumbnail sizes
+#[derive(Clone, Copy)]
+pub enum ThumbnailSize {
+    Small,
+    Poster,
+}
+
+impl ThumbnailSize {
+    fn width(self) -> u32 {
+        match self {
+            ThumbnailSize::Small => 320,
+            ThumbnailSize::Poster => 1280,
         }
     }
 
-    // Process each video that needs a thumbnail
-    for (id, video) in videos {
-        let thumb_path = thumbs_dir.join(format!("{}.jpg", id));
+    // AVIF encoding is slow, so only the small size gets it
+    fn formats(self) -> &'static [ThumbnailFormat] {
+        match self {
+            ThumbnailSize::Small => &ThumbnailFormat::ALL,
+            ThumbnailSize::Poster => &[ThumbnailFormat::Webp, ThumbnailFormat::Jpeg],
+        }
+    }
 
-        // Skip if thumbnail already exists
-        if fs::metadata(&thumb_path).await.is_ok() {
-            continue;
+    fn suffix(self) -> &'static str {
+        match self {
+            ThumbnailSize::Small => "",
+            ThumbnailSize::Poster => "-poster",
         }
+    }
+}
 
-        // Generate thumbnail in the background
-        let video_path = Path::new(&CONFIG.converted_dir).join(&video.filename);
+// Generated thumbnail formats, JPEG is always available as the fallback
+#[derive(Clone, Copy, PartialEq)]
+pub enum ThumbnailFormat {
+    Avif,
+    Webp,
+    Jpeg,
+}
+
+impl ThumbnailFormat {
+    // Ordered by preference when negotiating with the client
+    pub const ALL: [ThumbnailFormat; 3] = [
+        ThumbnailFormat::Avif,
+        ThumbnailFormat::Webp,
+        ThumbnailFormat::Jpeg,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Avif => "avif",
+            ThumbnailFormat::Webp => "webp",
+            ThumbnailFormat::Jpeg => "jpg",
+        }
+    }
 
-        info!("Generating thumbnail for video: {}", id);
+    pub fn mime(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Avif => "image/avif",
+            ThumbnailFormat::Webp => "image/webp",
+            ThumbnailFormat::Jpeg => "image/jpeg",
+        }
+    }
 
-        if let Err(e) = generate_thumbnail(&video_path, &thumb_path).await {
-            error!("Failed to generate thumbnail for {}: {}", id, e);
+    fn encoder_args(self) -> &'static [&'static str] {
+        match self {
+            ThumbnailFormat::Avif => &[
+                "-c:v",
+                "libaom-av1",
+                "-still-picture",
+                "1",
+                "-crf",
+                "32",
+                "-cpu-used",
+                "6",
+            ],
+            ThumbnailFormat::Webp => &["-c:v", "libwebp", "-quality", "80"],
+            ThumbnailFormat::Jpeg => &["-q:v", "2"],
         }
     }
 }
 
-// Function to generate a thumbnail from a video
-pub async fn generate_thumbnail(
+// Path of a thumbnail variant for a video
+pub fn thumbnail_path(id: &str, size: ThumbnailSize, format: ThumbnailFormat) -> PathBuf {
+    thumbs_dir().join(format!("{}{}.{}", id, size.suffix(), format.extension()))
+}
+
+// Generate every thumbnail size and format for a video
+pub async fn generate_thumbnails(video_path: &Path, id: &str) -> Result<(), std::io::Error> {
+    let thumb_path = thumbnail_path(id, ThumbnailSize::Small, ThumbnailFormat::Jpeg);
+    if fs::metadata(&thumb_path).await.is_ok() {
+        return Ok(());
+    }
+
+    let frame_path = thumbs_dir().join(format!("{}.frame.png", id));
+    let result = generate_variants(video_path, &frame_path, id).await;
+
+    if let Err(e) = fs::remove_file(&frame_path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("Failed to remove frame {}: {}", frame_path.display(), e);
+    }
+
+    result
+}
+
+async fn generate_variants(
     video_path: &Path,
-    thumb_path: &Path,
+    frame_path: &Path,
+    id: &str,
 ) -> Result<(), std::io::Error> {
-    let output = Command::new("ffmpeg")
-        .args([
-            "-y",
+    extract_frame(video_path, frame_path).await?;
+
+    // The small JPEG is written last since its existence marks the video as done
+    for size in [ThumbnailSize::Poster, ThumbnailSize::Small] {
+        for &format in size.formats() {
+            let thumb_path = thumbnail_path(id, size, format);
+            let result = write_atomically(&thumb_path, |temp_path| async move {
+                encode_variant(frame_path, &temp_path, size, format).await
+            })
+            .await;
+
+            match result {
+                Ok(()) => {}
+                // Not every ffmpeg build ships with the AVIF and WebP encoders
+                Err(e) if format != ThumbnailFormat::Jpeg => {
+                    warn!(
+                        "Skipping {} thumbnail for {}: {}",
+                        format.extension(),
+                        id,
+                        e
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Extract a representative frame at poster resolution
+async fn extract_frame(video_path: &Path, frame_path: &Path) -> Result<(), std::io::Error> {
+    let duration = probe(video_path).await.ok().and_then(|info| info.duration);
+    let filter = format!(
+        "thumbnail={},scale='min({},iw)':-2",
+        SCENE_SAMPLE_FRAMES,
+        ThumbnailSize::Poster.width()
+    );
+
+    let mut attempts = Vec::new();
+    match duration {
+        Some(duration) if duration >= MIN_SEEKABLE_DURATION => {
+            let offset = format!("{:.3}", duration * FRAME_POSITION);
+            attempts.push((Some(offset), filter.clone()));
+            attempts.push((None, filter));
+        }
+        // Very short or unprobeable clips are sampled from the beginning
+        _ => attempts.push((None, filter)),
+    }
+    // Last resort: the very first decodable frame
+    attempts.push((
+        None,
+        format!("scale='min({},iw)':-2", ThumbnailSize::Poster.width()),
+    ));
+
+    let mut last_error = None;
+    for (offset, filter) in &attempts {
+        let mut args = vec!["-y"];
+        if let Some(offset) = offset {
+            args.extend(["-ss", offset]);
+        }
+        args.extend([
             "-i",
             video_path.to_str().unwrap(),
-            "-ss",
-            "00:00:01",
-            "-vframes",
-            "1",
             "-vf",
-            "scale=320:-1", // Scale to 320px width, maintain aspect ratio
-            "-q:v",
-            "2",                          // High quality
-            thumb_path.to_str().unwrap(), // Output thumbnail path
-        ])
-        .output()
-        .await?;
-
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to generate thumbnail: {}", error),
-        ));
+            filter,
+            "-frames:v",
+            "1",
+            frame_path.to_str().unwrap(),
+        ]);
+
+        match run_checked(&args, None, "generate thumbnail").await {
+            // Seeking past the last keyframe can succeed without writing a frame
+            Ok(()) if fs::metadata(frame_path).await.is_ok() => return Ok(()),
+            Ok(()) => last_error = Some(std::io::Error::other("No frame was extracted")),
+            Err(e) => last_error = Some(e),
+        }
     }
 
-    Ok(())
+    Err(last_error.unwrap_or_else(|| std::io::Error::other("No frame was extracted")))
+}
+
+async fn encode_variant(
+    frame_path: &Path,
+    thumb_path: &Path,
+    size: ThumbnailSize,
+    format: ThumbnailFormat,
+) -> Result<(), std::io::Error> {
+    let filter = format!("scale='min({},iw)':-2", size.width());
+    let mut args = vec![
+        "-y",
+        "-i",
+        frame_path.to_str().unwrap(),
+        "-vf",
+        &filter,
+        "-frames:v",
+        "1",
+    ];
+    args.extend(format.encoder_args());
+    args.push(thumb_path.to_str().unwrap());
+
+    run_checked(&args, None, "generate thumbnail").await
 }
 
 // Function to get the list of videos
@@ -108,20 +267,71 @@ pub async fn get_video_list() -> Result<Vec<VideoInfo>, std::io::Error> {
         let metadata = fs::metadata(&path).await?;
         let created_at = metadata.created().unwrap_or(SystemTime::now());
 
+        let stored = VideoMetadata::load(&id).await;
         videos.push(VideoInfo {
             id,
             filename,
             thumbnail,
             created_at,
+            private: stored.as_ref().is_some_and(|stored| stored.private),
+            guild_ids: stored
+                .as_ref()
+                .map(VideoMetadata::guild_ids)
+                .unwrap_or_default(),
+            submitter_ids: stored
+                .as_ref()
+                .map(VideoMetadata::submitter_ids)
+                .unwrap_or_default(),
+            traceparent: stored.and_then(|stored| stored.traceparent),
         });
     }
 
+    // Remote backends may hold videos that aren't cached locally
+    if CONFIG.storage_backend != "local" {
+        match STORAGE.list("").await {
+            Ok(keys) => {
+                for key in keys {
+                    let Some(id) = key.strip_suffix(".mp4") else {
+                        continue;
+                    };
+                    if id.contains('/') || videos.iter().any(|video| video.id == id) {
+                        continue;
+                    }
+
+                    let stored = VideoMetadata::load(id).await;
+                    videos.push(VideoInfo {
+                        id: id.to_string(),
+                        filename: key.clone(),
+                        thumbnail: format!("thumbs/{}.jpg", id),
+                        created_at: SystemTime::UNIX_EPOCH,
+                        private: stored.as_ref().is_some_and(|stored| stored.private),
+                        guild_ids: stored
+                            .as_ref()
+                            .map(VideoMetadata::guild_ids)
+                            .unwrap_or_default(),
+                        submitter_ids: stored
+                            .as_ref()
+                            .map(VideoMetadata::submitter_ids)
+                            .unwrap_or_default(),
+                        traceparent: stored.and_then(|stored| stored.traceparent),
+                    });
+                }
+            }
+            Err(e) => error!("Failed to list videos in storage: {:?}", e),
+        }
+    }
+
     Ok(videos)
 }
 
+// Directory where generated thumbnails are stored
+pub fn thumbs_dir() -> PathBuf {
+    Path::new(&CONFIG.converted_dir).join("thumbs")
+}
+
 // Ensure thumbnails directory exists
 pub fn ensure_thumbs_dir() -> std::io::Result<PathBuf> {
-    let thumbs_dir = Path::new(&CONFIG.converted_dir).join("thumbs");
+    let thumbs_dir = thumbs_dir();
     if !thumbs_dir.exists() {
         std::fs::create_dir_all(&thumbs_dir)?;
     }
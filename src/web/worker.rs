@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tracing::{error, info, info_span, warn, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::CONFIG;
+use crate::metrics::THUMBNAILS_GENERATED;
+use crate::storage::{release_local, STORAGE};
+use crate::telemetry::{self, span_context};
+use crate::web::models::VideoInfo;
+use crate::web::previews::{generate_previews, preview_path, seek_track_path, sprite_path};
+use crate::web::thumbnails::{generate_thumbnails, thumbnail_path, ThumbnailFormat, ThumbnailSize};
+
+// Failure bookkeeping for a single video
+struct FailureEntry {
+    attempts: u32,
+    retry_at: Instant,
+    last_error: String,
+}
+
+// A video whose thumbnails couldn't be generated
+pub struct FailedThumbnail {
+    pub id: String,
+    pub attempts: u32,
+    pub retry_in: Duration,
+    pub error: String,
+}
+
+// Snapshot of the worker's queue for the admin dashboard
+pub struct WorkerStatus {
+    pub pending: Vec<String>,
+    pub failures: Vec<FailedThumbnail>,
+}
+
+#[derive(Default)]
+struct WorkerState {
+    // IDs that are queued, being processed or waiting for a retry
+    pending: HashSet<String>,
+    failures: HashMap<String, FailureEntry>,
+}
+
+// Background worker that generates thumbnails from a deduplicated queue
+#[derive(Clone)]
+pub struct ThumbnailWorker {
+    sender: mpsc::Sender<VideoInfo>,
+    state: Arc<Mutex<WorkerState>>,
+}
+
+impl ThumbnailWorker {
+    // Create the worker and spawn its processing loop
+    pub fn start() -> Self {
+        let (sender, receiver) = mpsc::channel(CONFIG.thumbnail_queue_size);
+        let worker = Self {
+            sender,
+            state: Arc::new(Mutex::new(WorkerState::default())),
+        };
+
+        tokio::spawn(worker.clone().run(receiver));
+        worker
+    }
+
+    // Queue thumbnail generation for every video that doesn't have one yet
+    pub async fn enqueue_missing(&self, videos: &HashMap<String, VideoInfo>) {
+        for (id, video) in videos {
+            let outputs = [
+                thumbnail_path(id, ThumbnailSize::Small, ThumbnailFormat::Jpeg),
+                preview_path(id),
+                sprite_path(id),
+                seek_track_path(id),
+            ];
+
+            let mut missing = false;
+            for output in &outputs {
+                if fs::metadata(output).await.is_err() {
+                    missing = true;
+                    break;
+                }
+            }
+
+            if missing {
+                self.enqueue(video.clone()).await;
+            }
+        }
+    }
+
+    // Queue a single video, skipping it if it's already queued or negatively cached
+    pub async fn enqueue(&self, video: VideoInfo) {
+        let mut state = self.state.lock().await;
+
+        if state.pending.contains(&video.id) {
+            return;
+        }
+
+        if let Some(failure) = state.failures.get(&video.id)
+            && failure.retry_at > Instant::now()
+        {
+            return;
+        }
+
+        // Never wait for room while holding the state, the next refresh queues it again
+        let id = video.id.clone();
+        match self.sender.try_send(video) {
+            Ok(()) => {
+                state.pending.insert(id);
+            }
+            Err(TrySendError::Full(_)) => warn!("Thumbnail queue is full, skipping {}", id),
+            Err(TrySendError::Closed(_)) => error!("Thumbnail worker is not running"),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.sender.is_closed()
+    }
+
+    pub async fn status(&self) -> WorkerStatus {
+        let state = self.state.lock().await;
+        let now = Instant::now();
+
+        let mut pending: Vec<_> = state.pending.iter().cloned().collect();
+        pending.sort();
+
+        let mut failures: Vec<_> = state
+            .failures
+            .iter()
+            .map(|(id, failure)| FailedThumbnail {
+                id: id.clone(),
+                attempts: failure.attempts,
+                retry_in: failure.retry_at.saturating_duration_since(now),
+                error: failure.last_error.clone(),
+            })
+            .collect();
+        failures.sort_by(|a, b| a.id.cmp(&b.id));
+
+        WorkerStatus { pending, failures }
+    }
+
+    // Drop any negative caching of a video so it's picked up again
+    pub async fn forget(&self, id: &str) {
+        self.state.lock().await.failures.remove(id);
+    }
+
+    async fn run(self, mut receiver: mpsc::Receiver<VideoInfo>) {
+        let semaphore = Arc::new(Semaphore::new(CONFIG.thumbnail_concurrency.max(1)));
+
+        while let Some(video) = receiver.recv().await {
+            let permit = match semaphore.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => break,
+            };
+
+            let worker = self.clone();
+            // Carry the posting of the video and link back to its conversion
+            let span = info_span!(
+                "thumbnail",
+                id = video.id.as_str(),
+                guild_id = video.guild_ids.first(),
+                user_id = video.submitter_ids.first()
+            );
+            if telemetry::enabled()
+                && let Some(traceparent) = &video.traceparent
+            {
+                span.add_link(span_context(traceparent));
+            }
+            tokio::spawn(
+                async move {
+                    worker.process(video).await;
+                    drop(permit);
+                }
+                .instrument(span),
+            );
+        }
+    }
+
+    async fn process(&self, video: VideoInfo) {
+        let video_path = Path::new(&CONFIG.converted_dir).join(&video.filename);
+
+        // Videos that only exist in remote storage are fetched for the duration of the work
+        let fetched = fs::metadata(&video_path).await.is_err();
+        if fetched && let Err(e) = STORAGE.fetch(&video.filename, &video_path).await {
+            self.record_failure(video, e.to_string()).await;
+            return;
+        }
+
+        info!("Generating thumbnails for video: {}", video.id);
+
+        let result = match generate_thumbnails(&video_path, &video.id).await {
+            Ok(()) => generate_previews(&video_path, &video.id).await,
+            Err(e) => Err(e),
+        };
+        if fetched {
+            release_local(&video.id).await;
+        }
+
+        match result {
+            Ok(()) => {
+                THUMBNAILS_GENERATED.inc();
+                let mut state = self.state.lock().await;
+                state.failures.remove(&video.id);
+                state.pending.remove(&video.id);
+            }
+            Err(e) => self.record_failure(video, e.to_string()).await,
+        }
+    }
+
+    async fn record_failure(&self, video: VideoInfo, error: String) {
+        let mut state = self.state.lock().await;
+
+        let attempts = state
+            .failures
+            .get(&video.id)
+            .map(|failure| failure.attempts)
+            .unwrap_or(0)
+            + 1;
+
+        if attempts >= CONFIG.thumbnail_max_attempts {
+            // Give up for now and keep the video out of the queue for a while
+            warn!(
+                "Giving up on thumbnail for {} after {} attempts: {}",
+                video.id, attempts, error
+            );
+            state.failures.insert(
+                video.id.clone(),
+                FailureEntry {
+                    attempts: 0,
+                    retry_at: Instant::now() + CONFIG.thumbnail_negative_ttl,
+                    last_error: error,
+                },
+            );
+            state.pending.remove(&video.id);
+            return;
+        }
+
+        // Never wait longer than a video that gave up would
+        let backoff = CONFIG
+            .thumbnail_retry_backoff
+            .saturating_mul(2u32.checked_pow(attempts - 1).unwrap_or(u32::MAX))
+            .min(CONFIG.thumbnail_negative_ttl);
+        error!(
+            "Failed to generate thumbnail for {} (attempt {}), retrying in {:?}: {}",
+            video.id, attempts, backoff, error
+        );
+        state.failures.insert(
+            video.id.clone(),
+            FailureEntry {
+                attempts,
+                retry_at: Instant::now() + backoff,
+                last_error: error,
+            },
+        );
+
+        // The ID stays pending while waiting so refreshes don't queue it twice
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(backoff).await;
+            sender.send(video).await.ok();
+        });
+    }
+}